@@ -0,0 +1,173 @@
+use crate::size::{DirSize, dir_size};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Location of the sccache local disk cache, `SCCACHE_DIR` overrides the platform default.
+pub fn sccache_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("SCCACHE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    let cache = dirs::cache_dir()?;
+    Some(if cfg!(target_os = "macos") {
+        cache.join("Mozilla.sccache")
+    } else if cfg!(windows) {
+        cache.join("Mozilla").join("sccache").join("cache")
+    } else {
+        cache.join("sccache")
+    })
+}
+
+/// `CARGO_HOME`, or `~/.cargo` when it isn't set.
+pub fn cargo_home() -> Option<PathBuf> {
+    std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")))
+}
+
+/// The cache directories below the cargo home, in the order they are reported.
+const CARGO_CACHE_DIRS: [&str; 3] = ["registry/cache", "registry/src", "git/checkouts"];
+
+/// Downloaded crates (`registry/cache`), their extracted sources (`registry/src`) and
+/// git checkouts (`git/checkouts`) in the cargo home not modified since `before`.
+///
+/// Every entry is a single `.crate` file or directory cargo recreates when it needs it
+/// again. Without `before` all of them are returned. With `keep_downloads` the `.crate`
+/// files are left out, so that only what cargo can rebuild without the network goes.
+pub fn old_cargo_cache_entries(
+    home: &Path,
+    before: Option<SystemTime>,
+    keep_downloads: bool,
+) -> Vec<PathBuf> {
+    CARGO_CACHE_DIRS
+        .iter()
+        .filter(|&&dir| !(keep_downloads && dir == "registry/cache"))
+        .filter_map(|dir| fs::read_dir(home.join(dir)).ok())
+        .flatten()
+        .filter_map(|source| fs::read_dir(source.ok()?.path()).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            before.is_none_or(|before| {
+                entry
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .is_ok_and(|modified| modified < before)
+            })
+        })
+        .map(|entry| entry.path())
+        .collect()
+}
+
+/// Size of `entries` in each of the cargo cache directories, leaving out those without any.
+pub fn cargo_cache_breakdown(home: &Path, entries: &[PathBuf]) -> Vec<(&'static str, DirSize)> {
+    CARGO_CACHE_DIRS
+        .iter()
+        .filter_map(|&dir| {
+            let dir_entries: Vec<_> = entries
+                .iter()
+                .filter(|entry| entry.starts_with(home.join(dir)))
+                .cloned()
+                .collect();
+            (!dir_entries.is_empty()).then(|| (dir, entries_size(&dir_entries)))
+        })
+        .collect()
+}
+
+/// Combined size of files and whole directories.
+pub fn entries_size(entries: &[PathBuf]) -> DirSize {
+    entries
+        .iter()
+        .map(|path| dir_size(path))
+        .fold(DirSize::default(), |acc, size| DirSize {
+            files: acc.files + size.files,
+            bytes: acc.bytes + size.bytes,
+        })
+}
+
+/// Remove files and whole directories.
+pub fn remove_entries(entries: &[PathBuf]) -> io::Result<()> {
+    for path in entries {
+        if path.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove everything inside `dir` while keeping the directory itself.
+///
+/// With `dry_run` the contents are only measured.
+pub fn clear_dir_contents(dir: &Path, dry_run: bool) -> anyhow::Result<DirSize> {
+    let size = dir_size(dir);
+    if dry_run {
+        return Ok(size);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cargo_cache_entries_one_level_below_each_source() {
+        let home = tempfile::tempdir().unwrap();
+        let home = home.path();
+        let index = home.join("registry/cache/index.crates.io-6f17d22bba15001f");
+        fs::create_dir_all(&index).unwrap();
+        fs::write(index.join("serde-1.0.0.crate"), [0u8; 512]).unwrap();
+        let src = home.join("registry/src/index.crates.io-6f17d22bba15001f/serde-1.0.0");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("lib.rs"), [0u8; 512]).unwrap();
+        fs::create_dir_all(home.join("git/checkouts/repo-0123/abcdef")).unwrap();
+        fs::create_dir_all(home.join("bin")).unwrap();
+
+        let mut entries = old_cargo_cache_entries(home, None, false);
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                home.join("git/checkouts/repo-0123/abcdef"),
+                index.join("serde-1.0.0.crate"),
+                src.clone(),
+            ]
+        );
+        assert!(old_cargo_cache_entries(home, Some(SystemTime::UNIX_EPOCH), false).is_empty());
+        let mut sources = old_cargo_cache_entries(home, None, true);
+        sources.sort();
+        assert_eq!(
+            sources,
+            [home.join("git/checkouts/repo-0123/abcdef"), src.clone()]
+        );
+
+        let size = entries_size(&entries);
+        assert_eq!((size.files, size.bytes), (2, 1024));
+        let breakdown: Vec<_> = cargo_cache_breakdown(home, &entries)
+            .into_iter()
+            .map(|(dir, size)| (dir, size.bytes))
+            .collect();
+        assert_eq!(
+            breakdown,
+            [
+                ("registry/cache", 512),
+                ("registry/src", 512),
+                ("git/checkouts", 0)
+            ]
+        );
+        remove_entries(&entries).unwrap();
+        assert!(!src.exists());
+        assert!(home.join("bin").exists());
+    }
+}
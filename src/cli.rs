@@ -0,0 +1,511 @@
+use crate::report::SummaryTemplate;
+use crate::time::TimeParser;
+use clap::{Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use regex::Regex;
+use std::path::{MAIN_SEPARATOR, PathBuf};
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Base directory to run cleanup scan, a leading `~` is expanded to the home directory
+    #[clap(value_parser = expand_dir)]
+    pub dir: Option<PathBuf>,
+    /// Clean only the project of this `Cargo.toml` without scanning, the filters still apply
+    #[clap(long, value_name = "PATH", value_parser = expand_dir, conflicts_with = "dir")]
+    pub manifest_path: Option<PathBuf>,
+    /// When the current directory is a project, clean just it instead of scanning below it
+    ///
+    /// Has no effect when a directory is given, which is always scanned.
+    #[clap(long = "self")]
+    pub only_self: bool,
+    /// Only clean project that hasn't been touched for a certain period, available units: m, h, d, w, mo (30 days), y (365 days)
+    ///
+    /// Units are case-insensitive, except that `M` is months while `m` is minutes.
+    #[clap(short, long, visible_alias = "modified-before")]
+    pub past: Option<String>,
+    /// Only clean projects that have been touched within a certain period, same units as --past
+    ///
+    /// Combined with --past this selects a window: projects last modified more than --past but
+    /// less than --newer-than ago.
+    #[clap(long, value_name = "DURATION", visible_alias = "modified-after")]
+    pub newer_than: Option<String>,
+    /// Use a --past of its own for projects under a directory, e.g. `~/tmp:3d`, repeatable
+    ///
+    /// The rule with the longest matching directory applies, projects outside all of them go
+    /// by --past or --min-age.
+    #[clap(long, value_name = "DIR:DURATION", value_parser = parse_rule)]
+    pub rule: Vec<(PathBuf, Duration)>,
+    /// Only clean projects idle for at least this long, same units as --past
+    ///
+    /// Together with --max-age this selects an age band, an alternative to --past and
+    /// --newer-than.
+    #[clap(long, value_name = "DURATION", conflicts_with_all = ["past", "newer_than"])]
+    pub min_age: Option<String>,
+    /// Only clean projects idle for at most this long, same units as --past
+    #[clap(long, value_name = "DURATION", conflicts_with_all = ["past", "newer_than"])]
+    pub max_age: Option<String>,
+    /// With --past or --min-age, also require the last access time to be older, sparing projects that are
+    /// still read but not modified
+    ///
+    /// Where access times aren't available this falls back to the modification time alone.
+    #[clap(long)]
+    pub older_than_accessed: bool,
+    /// Only clean projects not accessed within this period, same units as --past
+    ///
+    /// Combines with the modification time filters, a project has to pass all of them. Where
+    /// access times aren't available this filter is ignored.
+    #[clap(long, value_name = "DURATION")]
+    pub accessed_before: Option<String>,
+    /// Only clean projects accessed within this period, same units as --past
+    #[clap(long, value_name = "DURATION")]
+    pub accessed_after: Option<String>,
+    /// Skip projects when the filesystem doesn't track modification times, instead of cleaning
+    /// them regardless of --past and --newer-than
+    #[clap(long)]
+    pub skip_without_mtime: bool,
+    /// Use the time of the last git commit touching a project instead of its modification time
+    ///
+    /// Applies to --past, --newer-than, --keep-latest and --sort mtime. Projects outside a git
+    /// repository or without commits fall back to the modification time.
+    #[clap(long)]
+    pub git_commit_time: bool,
+    /// Which of a project's timestamps --past and friends compare against
+    ///
+    /// Access times reflect running tests or reading code without editing it, where the
+    /// filesystem records them. On a `noatime` mount or where they aren't available this falls
+    /// back to the modification time with a warning.
+    #[clap(
+        long,
+        value_enum,
+        value_name = "FIELD",
+        default_value_t = AgeField::Mtime,
+        conflicts_with = "git_commit_time"
+    )]
+    pub age_field: AgeField,
+    /// Judge a project by the newest file in it instead of its directory's own timestamp
+    ///
+    /// Editing a file doesn't change the modification time of the project directory, only
+    /// that of the directory holding the file. This walks the whole project, leaving out
+    /// target directories so builds don't count as use, and is slower accordingly.
+    #[clap(long)]
+    pub deep_mtime: bool,
+    /// Only clean projects whose path matches this glob, can be repeated
+    ///
+    /// Globs are matched against the project path relative to the base directory as well as
+    /// against the full path. Excludes take precedence: a project matching both an include and
+    /// an exclude glob is skipped.
+    #[clap(short, long, value_name = "GLOB")]
+    pub include: Vec<String>,
+    /// Skip projects whose path matches this glob, can be repeated
+    #[clap(short, long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+    /// Read exclude globs from this file, one per line, gitignore style
+    ///
+    /// A line starting with `!` includes what earlier lines excluded, the last matching line
+    /// wins. Blank lines and lines starting with `#` are skipped. --exclude still always wins.
+    #[clap(long, value_name = "PATH")]
+    pub pattern_file: Option<PathBuf>,
+    /// Skip projects whose `[package] name` in Cargo.toml is this, can be repeated
+    #[clap(long, value_name = "NAME")]
+    pub exclude_name: Vec<String>,
+    /// Only clean projects whose `[package] name` matches this regex
+    ///
+    /// Workspaces match when any of their members does, virtual ones are matched on
+    /// member names alone.
+    #[clap(long, value_name = "REGEX", value_parser = Regex::new)]
+    pub name: Option<Regex>,
+    /// Don't look for projects nested inside another project, e.g. fixtures or examples
+    /// with their own Cargo.toml
+    #[clap(long)]
+    pub no_nested: bool,
+    /// Skip crates that are members of a workspace further up, only cleaning workspace roots
+    /// and standalone crates
+    ///
+    /// Members build into the workspace's target, so one of their own is usually left over
+    /// from before they joined. Membership is read from the manifests like cargo does.
+    #[clap(long)]
+    pub exclude_workspace_members: bool,
+    /// Read directories on several threads while scanning, faster for huge trees on SSDs
+    #[clap(long)]
+    pub parallel_walk: bool,
+    /// Don't look for projects inside git submodules listed in `.gitmodules` files
+    #[clap(long)]
+    pub skip_submodules: bool,
+    /// Only remove `target/<profile>/incremental` directories, keeping built artifacts intact
+    #[clap(long)]
+    pub incremental_only: bool,
+    /// Only report the size of each selected project's target directory, without running cargo
+    ///
+    /// Sizes are cached between runs and only measured again once a target has changed.
+    #[clap(long, conflicts_with_all = ["incremental_only", "package", "target"])]
+    pub stats_only: bool,
+    /// Only list the projects that pass the filters, one path per line on stdout
+    ///
+    /// Neither cargo is run nor are sizes measured, except for --min-size, --fail-on-size and
+    /// --sort size which need them.
+    #[clap(
+        long,
+        visible_alias = "list",
+        conflicts_with_all = ["stats_only", "free_at_least", "format", "total_only", "summary_format"]
+    )]
+    pub scan_only: bool,
+    /// With --stats-only, measure every target again instead of reusing cached sizes
+    #[clap(long, requires = "stats_only")]
+    pub no_cache: bool,
+    /// Also clear the sccache directory (`SCCACHE_DIR` or the platform default location)
+    #[clap(long)]
+    pub sccache: bool,
+    /// Also remove cargo's downloaded crates, their sources and git checkouts in CARGO_HOME
+    /// downloaded or extracted before --past, after asking for confirmation
+    #[clap(long, visible_alias = "include-registry-cache")]
+    pub include_cargo_cache: bool,
+    /// With --include-cargo-cache, keep the downloaded `.crate` files in registry/cache and
+    /// only remove what cargo recreates without the network
+    #[clap(long, requires = "include_cargo_cache")]
+    pub keep_downloads: bool,
+    /// Don't ask for confirmation before removing cargo's cached downloads, and allow
+    /// scanning the filesystem root or the home directory
+    #[clap(short, long)]
+    pub yes: bool,
+    /// Allow scanning the filesystem root or the home directory itself
+    #[clap(long)]
+    pub allow_wide_scan: bool,
+    /// Retry `cargo clean` this many times when it fails because the target is locked or
+    /// because of an IO hiccup, other failures aren't retried
+    #[clap(long, value_name = "N", default_value_t = 2)]
+    pub retries: u32,
+    /// Only clean this package's artifacts in workspaces containing it, can be repeated
+    ///
+    /// Forwarded to `cargo clean -p <NAME>`. When given, only workspace roots are cleaned:
+    /// standalone crates and workspace members are skipped and counted as "not a workspace
+    /// root" in the summary, workspaces without the package are left alone. There is no `-p`
+    /// short form because `-p` is already `--past`.
+    #[clap(long, value_name = "NAME")]
+    pub package: Vec<String>,
+    /// Measure each target before cleaning and count all of it as freed once `cargo clean`
+    /// succeeds, instead of reading the size from cargo's output
+    ///
+    /// For cargo versions whose output is localized, suppressed or otherwise not understood.
+    #[clap(long, conflicts_with_all = ["package", "incremental_only", "stats_only"])]
+    pub assume_freed: bool,
+    /// Only clean artifacts built for this target triple, e.g. `wasm32-unknown-unknown`
+    ///
+    /// Removes `target/<TRIPLE>`, or is forwarded as `cargo clean --target <TRIPLE>` together
+    /// with --package.
+    #[clap(long, value_name = "TRIPLE", conflicts_with = "incremental_only")]
+    pub target: Option<String>,
+    /// Remove target directories that are left with only empty subdirectories after cleaning
+    #[clap(long)]
+    pub prune_empty_target: bool,
+    /// Also remove the Cargo.lock of every cleaned project, so the next build resolves the
+    /// dependencies afresh
+    ///
+    /// Crates that only build a library are left alone. Not done in dry runs.
+    #[clap(long)]
+    pub delete_lockfile: bool,
+    /// Stop at the first project that fails to clean instead of reporting it and carrying on
+    #[clap(long)]
+    pub fail_fast: bool,
+    /// Exit with an error when any target couldn't be cleaned for lack of permissions
+    ///
+    /// Such projects are listed in the summary either way, apart from the other failures.
+    #[clap(long)]
+    pub fail_on_permission_denied: bool,
+    /// Number of projects cleaned at the same time
+    #[clap(short, long, value_name = "N", default_value_t = 1)]
+    pub jobs: usize,
+    /// Let at most N of the --jobs projects remove files at the same time
+    ///
+    /// Defaults to --jobs, fine for SSDs. On a spinning disk concurrent deletes mostly add
+    /// seeks, 1 or 2 keeps it busy without thrashing while the other jobs wait.
+    #[clap(long, value_name = "N")]
+    pub io_parallelism: Option<usize>,
+    /// Limit each `cargo clean` to N threads of its own through `CARGO_BUILD_JOBS`
+    ///
+    /// At most --jobs times N threads then run at once.
+    #[clap(long, value_name = "N")]
+    pub threads_per_project: Option<usize>,
+    /// Only report what would be removed, without deleting anything
+    #[clap(short = 'n', long)]
+    pub dry_run: bool,
+    /// Output format of the per-project report written to stdout
+    #[clap(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+    /// Also write the run's totals as JSON to this file, replacing it on every run
+    #[clap(long, value_name = "PATH")]
+    pub summary_file: Option<PathBuf>,
+    /// Add the run's totals to the lifetime ones kept as JSON in this file
+    ///
+    /// Dry runs aren't counted. A file that can't be parsed is started afresh.
+    #[clap(long, value_name = "PATH")]
+    pub stats_file: Option<PathBuf>,
+    /// Print plain lines instead of a progress spinner, the default when stderr isn't a terminal
+    /// or `TERM` is `dumb`
+    #[clap(long)]
+    pub no_progress: bool,
+    /// Write `done/total` lines to this inherited file descriptor as projects are cleaned
+    ///
+    /// The total is known once the scan is done, which is announced with a `0/total` line.
+    /// An invalid descriptor only disables these lines with a warning. Unix only.
+    #[clap(long, value_name = "FD")]
+    pub progress_fd: Option<u32>,
+    /// Print nothing but a single plain summary line with the projects, files and size freed
+    #[clap(long, conflicts_with_all = ["format", "baseline"])]
+    pub total_only: bool,
+    /// Also tally why directories were skipped (no Cargo.toml, no target, too recent, ...)
+    /// and list the projects that were ignored or already clean
+    #[clap(short, long)]
+    pub verbose: bool,
+    /// Only print warnings and failures, and exit with an error if any project failed to clean
+    #[clap(long, conflicts_with_all = ["format", "total_only"])]
+    pub report_only_failures: bool,
+    /// How to print the final totals, by default matching --format
+    ///
+    /// `json` and `csv` go to stdout, with --format json the totals are part of the report
+    /// document. `csv` only combines with --format human.
+    #[clap(long, value_enum, value_name = "FORMAT", conflicts_with = "total_only")]
+    pub summary_format: Option<SummaryFormat>,
+    /// Replace the final summary line with this template
+    ///
+    /// Placeholders: {projects}, {files}, {size}, {bytes} and {elapsed}, e.g.
+    /// "freed {size} across {projects} projects in {elapsed}".
+    #[clap(long, value_name = "TEMPLATE", value_parser = SummaryTemplate::parse)]
+    pub summary_template: Option<SummaryTemplate>,
+    /// Also write the per-project lines and the summary to this file, without colors
+    #[clap(long, value_name = "PATH", value_parser = expand_dir)]
+    pub output: Option<PathBuf>,
+    /// Print sizes as whole bytes instead of KiB, MiB or GiB
+    #[clap(long, overrides_with = "human")]
+    pub bytes: bool,
+    /// Print sizes in KiB, MiB or GiB, the default
+    #[clap(long, overrides_with = "bytes")]
+    pub human: bool,
+    /// Write the report, i.e. what --format, --summary-format and --total-only print, to this
+    /// file instead of stdout, keeping the progress on the terminal
+    #[clap(long, value_name = "PATH", value_parser = expand_dir)]
+    pub output_file: Option<PathBuf>,
+    /// Compare this run against a previous `--format json` report and print what changed
+    #[clap(long, value_name = "FILE")]
+    pub baseline: Option<PathBuf>,
+    /// Run `cargo clean` in every directory with a `Cargo.toml`, even without a `target`
+    ///
+    /// Every crate is then counted as a project, including those with nothing to remove.
+    #[clap(long)]
+    pub no_target_required: bool,
+    /// Look for the target directory under this name instead of `target`, repeatable
+    ///
+    /// The first existing one of the names is cleaned, passing it to cargo as `--target-dir`.
+    /// `CARGO_TARGET_DIR` and `build.target-dir` still take precedence like for cargo.
+    #[clap(long, value_name = "NAME", alias = "include-target-name")]
+    pub target_dir_name: Vec<String>,
+    /// Abort without cleaning anything when fewer than N projects pass the filters
+    ///
+    /// A guard for scheduled runs, where a wrong path or glob would otherwise look like a
+    /// tree that is already clean.
+    #[clap(long, value_name = "N", alias = "expect-at-least")]
+    pub min_projects: Option<usize>,
+    /// Never clean the N most recently modified projects, ranked before the age and size
+    /// filters are applied
+    #[clap(long, value_name = "N")]
+    pub keep_latest: Option<usize>,
+    /// Only clean projects whose target directory is at least this large, e.g. 500MiB or 1GB
+    #[clap(long, value_name = "SIZE")]
+    pub min_size: Option<String>,
+    /// Exit with an error naming every project whose target is larger than this, e.g. 5GiB
+    ///
+    /// The projects are still cleaned, add --dry-run or --stats-only to only check them.
+    #[clap(long, value_name = "SIZE")]
+    pub fail_on_size: Option<String>,
+    /// Measure each target again after cleaning and warn when more than SIZE is left in it
+    ///
+    /// Catches deletes that silently failed, e.g. on network filesystems or because of files
+    /// locked by another process. Each project also gets a line comparing the space freed on
+    /// disk with what was reported. SIZE defaults to 1MiB.
+    #[clap(long, value_name = "SIZE", num_args = 0..=1, default_missing_value = "1MiB")]
+    pub verify: Option<String>,
+    /// Clean the largest projects first and stop once at least this much was freed, e.g. 20GiB
+    #[clap(long, value_name = "SIZE", conflicts_with = "stats_only")]
+    pub free_at_least: Option<String>,
+    /// Clean and report the projects in this order instead of the order they were found in
+    ///
+    /// With --jobs the lines still appear as the projects finish.
+    #[clap(long, value_enum, conflicts_with = "free_at_least")]
+    pub sort: Option<SortOrder>,
+    /// With --jobs, print the per-project lines in a stable order once all are cleaned
+    ///
+    /// The order is the one a single job prints them in, the walk order unless --sort says
+    /// otherwise. Warnings still appear right away.
+    #[clap(long)]
+    pub stable_order: bool,
+    /// Word to label the per-project lines and the cleaning spinner with, e.g. "Pruning"
+    ///
+    /// Replaces "Removed", or "Would remove" in dry runs, and "Cleaning".
+    #[clap(long, value_name = "WORD")]
+    pub label: Option<String>,
+    /// Break the freed space down by profile (debug, release, doc) in the summary
+    #[clap(long)]
+    pub by_profile: bool,
+    /// Break the freed space down by the directory this many levels below the scanned one
+    ///
+    /// With projects in `~/src/<org>/<repo>`, scanning `~/src` with `--group-by 1` shows
+    /// the space freed under each organisation.
+    #[clap(long, value_name = "DEPTH")]
+    pub group_by: Option<usize>,
+    /// Also report how much free space the scanned filesystem actually gained over the run
+    ///
+    /// Unlike cargo's numbers this accounts for block sizes and hardlinks, but anything else
+    /// writing to the same disk meanwhile skews it.
+    #[clap(long, conflicts_with_all = ["dry_run", "stats_only"])]
+    pub real_freed: bool,
+    /// Only clean when less than SIZE is available on the scanned filesystem, e.g. 20GiB
+    ///
+    /// Otherwise nothing is done and the exit status is 0, for cron jobs that should only
+    /// act once the disk fills up. With --when-used-above too, either condition is enough.
+    #[clap(long, value_name = "SIZE")]
+    pub when_below: Option<String>,
+    /// Only clean when more than PERCENT of the scanned filesystem is in use, e.g. 90%
+    #[clap(long, value_name = "PERCENT")]
+    pub when_used_above: Option<String>,
+    /// Show how long each project took to clean and list the slowest ones in the summary
+    #[clap(long, visible_alias = "profile-timings")]
+    pub timings: bool,
+    /// Shell command run in each project after it was cleaned, `{path}` expands to the project path
+    ///
+    /// Not run with --dry-run. Failing commands are reported and counted in the summary.
+    #[clap(long, value_name = "CMD")]
+    pub exec: Option<String>,
+    /// Only measure these target directories and print their sizes, without walking DIR
+    /// or running cargo
+    ///
+    /// Meant for checking the size measurement itself. Honours --format json.
+    #[clap(long, value_name = "TARGET", num_args = 1.., conflicts_with_all = ["baseline", "summary_file"])]
+    pub measure_only_no_walk: Vec<PathBuf>,
+    /// Cargo binary used to run `cargo clean`
+    #[clap(long, env = "CARGO", value_name = "PATH", default_value = "cargo")]
+    pub cargo_path: PathBuf,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print a completion script for the given shell to stdout
+    #[command(hide = true)]
+    Completions { shell: Shell },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Styled lines on the terminal
+    Human,
+    /// A JSON document listing every cleaned project
+    Json,
+    /// The same JSON document, indented for reading
+    JsonPretty,
+    /// One JSON object per line as things happen, without a progress bar
+    Ndjson,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SummaryFormat {
+    /// A styled line on stderr
+    Human,
+    /// A JSON object
+    Json,
+    /// A header and a row of comma separated values
+    Csv,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// Alphabetically by path
+    Path,
+    /// Largest target first
+    Size,
+    /// Least recently modified first
+    Mtime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AgeField {
+    /// Last modification of the project directory
+    Mtime,
+    /// Last access of the project directory
+    Atime,
+    /// Last status change of the project directory, such as a rename or permission change
+    Ctime,
+}
+
+/// Split `DIR:DURATION` at the last colon, so Windows drive letters stay with the directory.
+fn parse_rule(arg: &str) -> Result<(PathBuf, Duration), String> {
+    let (dir, age) = arg
+        .rsplit_once(':')
+        .ok_or("expected DIR:DURATION, e.g. ~/tmp:3d")?;
+    let age = TimeParser::parse_duration(age).map_err(|e| e.to_string())?;
+    Ok((expand_dir(dir)?, age))
+}
+
+/// Expand a leading `~` the way a shell would, for when it was quoted and reached us
+/// verbatim, and drop trailing slashes.
+fn expand_dir(arg: &str) -> Result<PathBuf, String> {
+    let path = match arg.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', MAIN_SEPARATOR]) => {
+            let home = dirs::home_dir().ok_or("Failed to locate the home directory")?;
+            home.join(rest.trim_start_matches(['/', MAIN_SEPARATOR]))
+        }
+        _ => PathBuf::from(arg),
+    };
+    Ok(path.components().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tilde_expands_to_home() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_dir("~").unwrap(), home);
+        assert_eq!(expand_dir("~/").unwrap(), home);
+        assert_eq!(expand_dir("~/code/rust").unwrap(), home.join("code/rust"));
+    }
+
+    #[test]
+    fn other_paths_are_kept() {
+        assert_eq!(expand_dir("code/").unwrap(), PathBuf::from("code"));
+        assert_eq!(expand_dir("/tmp//ws/").unwrap(), PathBuf::from("/tmp/ws"));
+        assert_eq!(
+            expand_dir("~user/code").unwrap(),
+            PathBuf::from("~user/code")
+        );
+        assert_eq!(expand_dir("a/~").unwrap(), PathBuf::from("a/~"));
+    }
+
+    #[test]
+    fn rules_split_at_the_last_colon() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            parse_rule("~/tmp:3d").unwrap(),
+            (home.join("tmp"), Duration::from_secs(3 * 24 * 3600))
+        );
+        assert_eq!(
+            parse_rule(r"C:\work:1h").unwrap().0,
+            PathBuf::from(r"C:\work")
+        );
+        assert!(parse_rule("~/tmp").is_err());
+        assert!(parse_rule("~/tmp:soon").is_err());
+    }
+
+    #[test]
+    fn completions_cover_every_shell() {
+        use clap::CommandFactory;
+        for shell in Shell::value_variants() {
+            let mut script = Vec::new();
+            clap_complete::generate(*shell, &mut Cli::command(), "cargo-clean-plus", &mut script);
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("dry-run"), "{shell}");
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::cli::Cli;
+use anyhow::{Context, bail};
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the per-tree config file, looked up in the scanned directory.
+const CONFIG_FILE: &str = ".cargo-clean-plus.toml";
+
+/// Defaults for command line options, read from `.cargo-clean-plus.toml`.
+///
+/// Keys are named like the long options, e.g. `min-size = "500MiB"`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    past: Option<String>,
+    newer_than: Option<String>,
+    min_age: Option<String>,
+    max_age: Option<String>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    exclude_name: Option<Vec<String>>,
+    min_size: Option<String>,
+    free_at_least: Option<String>,
+    keep_latest: Option<usize>,
+    when_below: Option<String>,
+    when_used_above: Option<String>,
+    jobs: Option<usize>,
+    io_parallelism: Option<usize>,
+    threads_per_project: Option<usize>,
+    retries: Option<u32>,
+    deep_mtime: Option<bool>,
+    no_nested: Option<bool>,
+    exclude_workspace_members: Option<bool>,
+    skip_submodules: Option<bool>,
+    incremental_only: Option<bool>,
+    prune_empty_target: Option<bool>,
+    sccache: Option<bool>,
+}
+
+impl Config {
+    /// The user wide config, then the one in the scanned directory, so that the latter wins.
+    fn paths(dir: &Path) -> Vec<PathBuf> {
+        let user = dirs::config_dir().map(|config| config.join("cargo-clean-plus/config.toml"));
+        user.into_iter().chain([dir.join(CONFIG_FILE)]).collect()
+    }
+
+    fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let config = match fs::read_to_string(path) {
+            Ok(config) => config,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let config = toml::from_str(&config)
+            .with_context(|| format!("Invalid config {}", path.display()))?;
+        Ok(Some(config))
+    }
+
+    /// Fill in every option not given on the command line (or through its environment
+    /// variable) from the config files that exist.
+    pub fn apply(cli: &mut Cli, matches: &ArgMatches, dir: &Path) -> anyhow::Result<()> {
+        let explicit = |id: &str| {
+            matches
+                .value_source(id)
+                .is_some_and(|source| source != ValueSource::DefaultValue)
+        };
+        // --past/--newer-than and --min-age/--max-age exclude each other, so a pair given
+        // on the command line keeps the other one out of the configs too
+        let explicit_past = explicit("past") || explicit("newer_than");
+        let explicit_band = explicit("min_age") || explicit("max_age");
+        for path in Self::paths(dir) {
+            let Some(mut config) = Self::load(&path)? else {
+                continue;
+            };
+            let config_past = config.past.is_some() || config.newer_than.is_some();
+            let config_band = config.min_age.is_some() || config.max_age.is_some();
+            if config_past && config_band {
+                bail!(
+                    "Invalid config {}: past and newer-than can't be combined with min-age and max-age",
+                    path.display()
+                );
+            }
+            if explicit_past || (config_past && !explicit_band) {
+                (config.min_age, config.max_age) = (None, None);
+                if !explicit_band {
+                    // a later config choosing the other pair replaces an earlier one's
+                    (cli.min_age, cli.max_age) = (None, None);
+                }
+            }
+            if explicit_band || (config_band && !explicit_past) {
+                (config.past, config.newer_than) = (None, None);
+                if !explicit_past {
+                    (cli.past, cli.newer_than) = (None, None);
+                }
+            }
+            macro_rules! apply {
+                (optional: $($opt:ident),*; required: $($val:ident),*) => {
+                    $(
+                        if let Some(value) = config.$opt
+                            && !explicit(stringify!($opt))
+                        {
+                            cli.$opt = Some(value);
+                        }
+                    )*
+                    $(
+                        if let Some(value) = config.$val
+                            && !explicit(stringify!($val))
+                        {
+                            cli.$val = value;
+                        }
+                    )*
+                };
+            }
+            apply!(
+                optional: past, newer_than, min_age, max_age, min_size, free_at_least, keep_latest,
+                    when_below, when_used_above, threads_per_project, io_parallelism;
+                required: include, exclude, exclude_name, jobs, retries, deep_mtime, no_nested,
+                    exclude_workspace_members, skip_submodules, incremental_only, prune_empty_target, sccache
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn apply(args: &[&str], config: &str) -> Cli {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(CONFIG_FILE), config).unwrap();
+        let matches = Cli::command().get_matches_from(args);
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        Config::apply(&mut cli, &matches, dir.path()).unwrap();
+        cli
+    }
+
+    #[test]
+    fn config_fills_in_defaults() {
+        let cli = apply(
+            &["cargo-clean-plus"],
+            "past = \"2w\"\nexclude = [\"vendor/**\"]\njobs = 4\nno-nested = true",
+        );
+        assert_eq!(cli.past.as_deref(), Some("2w"));
+        assert_eq!(cli.exclude, ["vendor/**"]);
+        assert_eq!(cli.jobs, 4);
+        assert!(cli.no_nested);
+    }
+
+    #[test]
+    fn command_line_wins() {
+        let cli = apply(
+            &["cargo-clean-plus", "--past", "1d", "-j", "2", "-e", "tmp"],
+            "past = \"2w\"\nexclude = [\"vendor/**\"]\njobs = 4",
+        );
+        assert_eq!(cli.past.as_deref(), Some("1d"));
+        assert_eq!(cli.exclude, ["tmp"]);
+        assert_eq!(cli.jobs, 2);
+    }
+
+    #[test]
+    fn command_line_age_pair_keeps_the_other_out() {
+        let cli = apply(
+            &["cargo-clean-plus", "--past", "1d"],
+            "min-age = \"30d\"\nmax-age = \"1y\"",
+        );
+        assert_eq!(cli.past.as_deref(), Some("1d"));
+        assert_eq!((cli.min_age, cli.max_age), (None, None));
+
+        let cli = apply(
+            &["cargo-clean-plus", "--max-age", "1y"],
+            "past = \"2w\"\nnewer-than = \"1y\"",
+        );
+        assert_eq!(cli.max_age.as_deref(), Some("1y"));
+        assert_eq!((cli.past, cli.newer_than), (None, None));
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join(CONFIG_FILE),
+            "past = \"2w\"\nmin-age = \"1d\"",
+        )
+        .unwrap();
+        let matches = Cli::command().get_matches_from(["cargo-clean-plus"]);
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        assert!(Config::apply(&mut cli, &matches, dir.path()).is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(CONFIG_FILE), "pats = \"2w\"").unwrap();
+        assert!(Config::load(&dir.path().join(CONFIG_FILE)).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use std::io;
+use thiserror::Error;
+
+/// Failures of the core operations, so callers can tell them apart instead of
+/// matching on messages.
+#[derive(Debug, Error)]
+pub enum CleanError {
+    /// A duration like the one given to `--past` couldn't be parsed.
+    #[error("Invalid duration `{input}`, {reason}")]
+    InvalidDuration { input: String, reason: &'static str },
+    /// `cargo clean` exited with an error. `summary` is its first line, the full
+    /// output is the source.
+    #[error("{summary}")]
+    CargoFailed {
+        summary: String,
+        #[source]
+        stderr: CargoStderr,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// `cargo clean` succeeded but what it printed couldn't be understood.
+    #[error("Failed to parse cargo clean output: {0}")]
+    ParseOutput(String),
+}
+
+// stderr fragments of cargo failing to remove files it isn't allowed to, e.g. in a target
+// owned by another user. The error numbers mean other things on the other platform.
+#[cfg(unix)]
+const PERMISSION_ERRORS: &[&str] = &[
+    "Permission denied",
+    "Operation not permitted",
+    "os error 1)",
+    "os error 13)",
+];
+#[cfg(not(unix))]
+const PERMISSION_ERRORS: &[&str] = &["Access is denied", "os error 5)"];
+
+/// Whether cargo's error output says it wasn't allowed to remove some files.
+pub fn is_permission_error(stderr: &str) -> bool {
+    PERMISSION_ERRORS.iter().any(|error| stderr.contains(error))
+}
+
+impl CleanError {
+    /// Whether the clean failed because the files may not be removed by this user, as
+    /// opposed to a broken project.
+    pub fn is_permission_denied(&self) -> bool {
+        match self {
+            Self::Io(e) => e.kind() == io::ErrorKind::PermissionDenied,
+            Self::CargoFailed { stderr, .. } => is_permission_error(&stderr.0),
+            _ => false,
+        }
+    }
+}
+
+/// Cargo's complete error output.
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct CargoStderr(pub String);
@@ -0,0 +1,151 @@
+use anyhow::Context;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::fs;
+use std::path::Path;
+
+/// Include / exclude globs applied to project paths.
+///
+/// A project is kept if it matches at least one include glob (or no include
+/// globs were given) and matches none of the exclude globs, i.e. excludes
+/// always win over includes. Globs are tried against both the path relative to
+/// the scan directory and the full path.
+///
+/// Rules from a pattern file come in between: the last one matching a path decides
+/// whether it is excluded, a `!` rule taking back an earlier exclusion.
+#[derive(Default)]
+pub struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    rules: GlobSet,
+    /// Whether each rule, in file order, excludes (`false` for `!` rules).
+    rule_excludes: Vec<bool>,
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> anyhow::Result<Self> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(build_set(include)?)
+        };
+        Ok(Self {
+            include,
+            exclude: build_set(exclude)?,
+            ..Default::default()
+        })
+    }
+
+    /// Add the rules of a gitignore style pattern file: a glob per line excludes what it
+    /// matches, a leading `!` includes it again, blank lines and `#` comments are skipped.
+    pub fn with_pattern_file(mut self, path: &Path) -> anyhow::Result<Self> {
+        let patterns = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut builder = GlobSetBuilder::new();
+        for (i, line) in patterns.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (glob, excludes) = match line.strip_prefix('!') {
+                Some(glob) => (glob, false),
+                None => (line, true),
+            };
+            let glob = Glob::new(glob).with_context(|| {
+                format!("Invalid pattern on line {} of {}", i + 1, path.display())
+            })?;
+            builder.add(glob);
+            self.rule_excludes.push(excludes);
+        }
+        self.rules = builder.build()?;
+        Ok(self)
+    }
+
+    pub fn is_match(&self, base: &Path, path: &Path) -> bool {
+        let relative = path.strip_prefix(base).unwrap_or(path);
+        let matches = |set: &GlobSet| set.is_match(relative) || set.is_match(path);
+        if matches(&self.exclude) {
+            return false;
+        }
+        let last_rule = [relative, path]
+            .iter()
+            .flat_map(|path| self.rules.matches(path))
+            .max();
+        if last_rule.is_some_and(|rule| self.rule_excludes[rule]) {
+            return false;
+        }
+        self.include.as_ref().is_none_or(matches)
+    }
+}
+
+fn build_set(globs: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn globs(globs: &[&str]) -> Vec<String> {
+        globs.iter().map(|&glob| glob.to_owned()).collect()
+    }
+
+    #[test]
+    fn includes_keep_only_what_they_match() {
+        let filter = PathFilter::new(&globs(&["work/*"]), &[]).unwrap();
+        let base = Path::new("/src");
+        assert!(filter.is_match(base, Path::new("/src/work/app")));
+        assert!(!filter.is_match(base, Path::new("/src/play/app")));
+        // The full path is tried as well as the relative one.
+        let filter = PathFilter::new(&globs(&["/src/play/*"]), &[]).unwrap();
+        assert!(filter.is_match(base, Path::new("/src/play/app")));
+    }
+
+    #[test]
+    fn excludes_drop_what_they_match() {
+        let filter = PathFilter::new(&[], &globs(&["**/vendor/**"])).unwrap();
+        let base = Path::new("/src");
+        assert!(filter.is_match(base, Path::new("/src/app")));
+        assert!(!filter.is_match(base, Path::new("/src/app/vendor/serde")));
+    }
+
+    #[test]
+    fn excludes_win_over_includes() {
+        let filter = PathFilter::new(&globs(&["work/**"]), &globs(&["work/old/**"])).unwrap();
+        let base = Path::new("/src");
+        assert!(filter.is_match(base, Path::new("/src/work/app")));
+        assert!(!filter.is_match(base, Path::new("/src/work/old/app")));
+        assert!(!filter.is_match(base, Path::new("/src/play/app")));
+    }
+
+    #[test]
+    fn later_pattern_file_rules_win() {
+        let dir = tempfile::tempdir().unwrap();
+        let patterns = dir.path().join("patterns");
+        fs::write(
+            &patterns,
+            "# team rules\nvendor/**\n!vendor/ours\n\nvendor/ours/old\n",
+        )
+        .unwrap();
+        let filter = PathFilter::new(&[], &["tmp".to_owned()])
+            .unwrap()
+            .with_pattern_file(&patterns)
+            .unwrap();
+        let base = Path::new("/src");
+        assert!(filter.is_match(base, Path::new("/src/app")));
+        assert!(!filter.is_match(base, Path::new("/src/vendor/serde")));
+        assert!(filter.is_match(base, Path::new("/src/vendor/ours")));
+        assert!(!filter.is_match(base, Path::new("/src/vendor/ours/old")));
+        assert!(!filter.is_match(base, Path::new("/src/tmp")));
+
+        fs::write(&patterns, "ok/**\n[broken").unwrap();
+        let e = PathFilter::default()
+            .with_pattern_file(&patterns)
+            .err()
+            .unwrap();
+        assert!(format!("{e}").contains("line 2"), "{e}");
+    }
+}
@@ -1,125 +1,890 @@
-use clap::Parser;
+mod caches;
+mod cli;
+mod config;
+mod error;
+mod filter;
+mod project;
+mod report;
+mod scan;
+mod size;
+mod size_cache;
+mod stats;
+mod time;
+mod ui;
+
+use crate::caches::{
+    cargo_cache_breakdown, cargo_home, clear_dir_contents, entries_size, old_cargo_cache_entries,
+    remove_entries, sccache_dir,
+};
+use crate::cli::{Cli, Command, OutputFormat, SummaryFormat};
+use crate::config::Config;
+use crate::filter::PathFilter;
+use crate::project::{
+    CargoProject, CleanOptions, CleanOutput, TargetEnv, check_cargo, resolve_cargo,
+};
+use crate::report::{Event, Lifetime, Report, Sink, Summary};
+use crate::scan::{ScanOptions, process_directory};
+use crate::size::{dir_size, parse_percent, parse_size};
+use crate::size_cache::SizeCache;
+use crate::stats::{CleanupStats, SizeFormat};
+use crate::time::TimeParser;
+use crate::ui::{TICK_INTERVAL, Ui, open_progress_fd};
+use anyhow::{Context, bail};
+use clap::{CommandFactory, FromArgMatches};
 use console::style;
+use indicatif::{ProgressBar, ProgressStyle};
 use std::env::current_dir;
-use std::path::PathBuf;
-use std::process::Command;
-use anyhow::bail;
-use indicatif::ProgressStyle;
-use walkdir::WalkDir;
-
-#[derive(Parser)]
-#[command(version, about, long_about = None)]
-struct Cli {
-    /// Base directory to run cleanup scan
-    dir: Option<PathBuf>,
-    /// Only clean project that hasn't been touched for a certain period, available units: m, h, d, w
-    #[clap(short, long)]
-    past: Option<String>,
+use std::io::{IsTerminal, stderr, stdin, stdout};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How many of the slowest projects `--timings` lists in the summary.
+const SLOWEST_SHOWN: usize = 5;
+
+/// Exit status after Ctrl-C, 128 plus SIGINT like a shell reports it.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+fn setup_progress_bar() -> anyhow::Result<ProgressBar> {
+    let pb = ProgressBar::new_spinner();
+    // the spinner trails the message, finished lines keep the usual alignment
+    pb.set_style(ProgressStyle::with_template(
+        "{prefix:>12.bold.green} {msg} {spinner}",
+    )?);
+    // ticks stop with `finish_with_message` and `finish_and_clear`
+    pb.enable_steady_tick(TICK_INTERVAL);
+    Ok(pb)
+}
+
+/// Measure the given target directories as if they had been cleaned, for
+/// `--measure-only-no-walk`.
+fn measure_targets(
+    targets: &[PathBuf],
+    format: OutputFormat,
+    size_format: SizeFormat,
+    sink: &Sink,
+) -> anyhow::Result<()> {
+    let mut stats = CleanupStats {
+        size_format,
+        ..Default::default()
+    };
+    for target in targets {
+        if !target.is_dir() {
+            bail!("{} is not a directory", target.display());
+        }
+        let size = dir_size(target);
+        let out = CleanOutput {
+            files: size.files,
+            size_kib: size.size_kib(),
+            measured: true,
+            ..Default::default()
+        };
+        stats.record(target, &out);
+        if format == OutputFormat::Human {
+            sink.write_line(&format!(
+                "{:>12} {} files, {} total in {}",
+                style("Measured").bold().green(),
+                out.files,
+                stats.format_size(out.size_kib),
+                target.display()
+            ))?;
+        }
+    }
+    match format {
+        OutputFormat::Human => {
+            sink.write_line(&format!("{:>12} {}", style("Total").bold().green(), stats))?
+        }
+        OutputFormat::Json | OutputFormat::JsonPretty => {
+            let pretty = format == OutputFormat::JsonPretty;
+            sink.write_line(&Report::from(&stats).to_json(pretty)?)?
+        }
+        OutputFormat::Ndjson => {
+            for record in &stats.records {
+                Event::ProjectCleaned(record).emit(sink)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Ask a yes/no question on the terminal, anything but yes counts as no, as does
+/// running without a terminal to ask on.
+fn confirm(ui: &Ui, question: &str) -> anyhow::Result<bool> {
+    if !stdin().is_terminal() {
+        return Ok(false);
+    }
+    ui.suspend(|| {
+        eprint!("{question} [y/N] ");
+        let mut answer = String::new();
+        stdin().read_line(&mut answer)?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    })
+}
+
+/// Whether `dir` is the filesystem root or the home directory, where a scan covers far
+/// more than is likely intended.
+fn is_wide_scan(dir: &Path) -> bool {
+    let Ok(dir) = dir.canonicalize() else {
+        return false;
+    };
+    dir.parent().is_none()
+        || dirs::home_dir()
+            .and_then(|home| home.canonicalize().ok())
+            .is_some_and(|home| home == dir)
 }
 
 fn main() -> anyhow::Result<()> {
-    let cmd = Cli::parse();
-    let dir = cmd
-        .dir
-        .unwrap_or_else(|| current_dir().expect("Failed to get current directory"));
-
-    let past = cmd.past.as_deref().unwrap_or("0m");
-    let past = if past.ends_with('m') {
-        past.trim_end_matches('m').parse::<u64>()? * 60
-    } else if past.ends_with('h') {
-        past.trim_end_matches('h').parse::<u64>()? * 60 * 60
-    } else if past.ends_with('d') {
-        past.trim_end_matches('d').parse::<u64>()? * 60 * 60 * 24
-    } else if past.ends_with('w') {
-        past.trim_end_matches('w').parse::<u64>()? * 60 * 60 * 24 * 7
+    let started = Instant::now();
+    let matches = Cli::command().get_matches();
+    let mut cmd = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let size_format = if cmd.bytes {
+        SizeFormat::Bytes
     } else {
-        bail!("Unknown unit, available units: m, h, d, w");
+        SizeFormat::Human
     };
-    let now = std::time::SystemTime::now();
-    let before = now.checked_sub(std::time::Duration::from_secs(past)).unwrap_or(now);
-
-    let pb = indicatif::ProgressBar::new_spinner();
-    pb.set_style(ProgressStyle::with_template("{prefix:>12.bold.green} {msg}")?);
-
-    let mut cleaned_projects = 0usize;
-    let mut removed_files = 0usize;
-    let mut removed_size = 0.;
-    // cargo clean output format is: \s+ Removed \d+ files, \d+(.\d+)? KiB/MiB/GiB total
-    let re = regex::Regex::new(
-        r"Removed (?P<files>\d+) files, (?P<size>\d+(?:\.\d+)?)(?P<unit>\w+) total",
-    )?;
-
-    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
-        pb.set_prefix("Scanning");
-        pb.set_message(format!("{}", entry.path().display()));
-
-        // skip if not a cargo project
-        if !entry.path().join("Cargo.toml").exists() {
-            continue;
-        }
-        // skip if /target not exists or is empty
-        let target_dir = entry.path().join("target");
-        if !target_dir.exists() {
-            continue;
-        }
-        // skip if modified after the specified time
-        if let Some(modified) = entry.metadata().ok().and_then(|m| m.modified().ok()) {
-            if modified > before {
-                continue;
-            }
+    if let Some(Command::Completions { shell }) = cmd.command {
+        clap_complete::generate(
+            shell,
+            &mut Cli::command(),
+            env!("CARGO_BIN_NAME"),
+            &mut stdout(),
+        );
+        return Ok(());
+    }
+    let sink = Sink::new(cmd.output_file.as_deref())?;
+    if !cmd.measure_only_no_walk.is_empty() {
+        return measure_targets(&cmd.measure_only_no_walk, cmd.format, size_format, &sink);
+    }
+    if let Some(manifest) = &cmd.manifest_path
+        && (manifest.file_name() != Some("Cargo.toml".as_ref()) || !manifest.is_file())
+    {
+        bail!("--manifest-path must point to an existing Cargo.toml");
+    }
+    let project_dir = cmd.manifest_path.as_deref().and_then(|manifest| {
+        let dir = manifest.parent()?;
+        Some(if dir.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            dir.to_path_buf()
+        })
+    });
+    let dir_given = cmd.dir.is_some();
+    let dir = match cmd.dir.take().or(project_dir) {
+        Some(dir) => dir,
+        None => current_dir().context(
+            "Failed to get the current directory, pass the directory to scan as an argument",
+        )?,
+    };
+    // one spelling of the directory, so that `code/../code/` reports the same paths as `code`
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("Can't scan {}", dir.display()))?;
+    let target_env = TargetEnv::from_env();
+    // with --self, a current directory that is a project stands for just that project
+    let single_project = cmd.manifest_path.is_some()
+        || (cmd.only_self
+            && !dir_given
+            && CargoProject::new(&dir)
+                .with_target_names(&cmd.target_dir_name)
+                .with_target_env(&target_env)
+                .is_valid_project(!cmd.no_target_required)
+                .unwrap_or(false));
+    if !single_project && !cmd.yes && !cmd.allow_wide_scan && is_wide_scan(&dir) {
+        bail!(
+            "Refusing to scan {}, which would walk everything below it; pass --yes or --allow-wide-scan if that's intended",
+            dir.display()
+        );
+    }
+    Config::apply(&mut cmd, &matches, &dir)?;
+
+    let summary_format = cmd.summary_format.unwrap_or(match cmd.format {
+        OutputFormat::Human => SummaryFormat::Human,
+        OutputFormat::Json | OutputFormat::JsonPretty | OutputFormat::Ndjson => SummaryFormat::Json,
+    });
+    if summary_format == SummaryFormat::Csv && cmd.format != OutputFormat::Human {
+        bail!("--summary-format csv only combines with --format human, stdout would mix formats");
+    }
+    // the summary replaces the styled one on stderr when it goes to stdout on its own
+    let summary_on_stdout = summary_format != SummaryFormat::Human;
+
+    // how long projects must have been idle, and at most may have been idle
+    let (min_age, max_age) = if cmd.min_age.is_some() || cmd.max_age.is_some() {
+        let min_age = TimeParser::parse_duration(cmd.min_age.as_deref().unwrap_or("0m"))?;
+        let max_age = cmd
+            .max_age
+            .as_deref()
+            .map(TimeParser::parse_duration)
+            .transpose()?;
+        if max_age.is_some_and(|max_age| max_age < min_age) {
+            bail!("--max-age must not be shorter than --min-age, otherwise no project can match");
+        }
+        (min_age, max_age)
+    } else {
+        let past = TimeParser::parse_duration(cmd.past.as_deref().unwrap_or("0m"))?;
+        let newer_than = cmd
+            .newer_than
+            .as_deref()
+            .map(TimeParser::parse_duration)
+            .transpose()?;
+        if newer_than.is_some_and(|newer_than| newer_than <= past) {
+            bail!("--newer-than must be longer than --past, otherwise no project can match");
         }
+        (past, newer_than)
+    };
+    let now = SystemTime::now();
+    // without a minimum age there is nothing to compare, so projects are taken whatever their mtime
+    let band = cmd.min_age.is_some() || cmd.max_age.is_some();
+    let (min_option, max_option) = if band {
+        ("--min-age", "--max-age")
+    } else {
+        ("--past", "--newer-than")
+    };
+    let before = (!min_age.is_zero()).then(|| before_bound(now, min_age, min_option));
+    let after = max_age.map(|max_age| after_bound(now, max_age, max_option));
+    // canonical like the project paths they are matched against
+    let rules = cmd
+        .rule
+        .iter()
+        .map(|(dir, age)| {
+            let dir = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+            let before = before_bound(now, *age, &format!("--rule for {}", dir.display()));
+            (dir, before)
+        })
+        .collect();
+    let parse = |duration: &Option<String>| {
+        duration
+            .as_deref()
+            .map(TimeParser::parse_duration)
+            .transpose()
+    };
+    let accessed_before =
+        parse(&cmd.accessed_before)?.map(|age| before_bound(now, age, "--accessed-before"));
+    let accessed_after =
+        parse(&cmd.accessed_after)?.map(|age| after_bound(now, age, "--accessed-after"));
+    if let (Some(before), Some(after)) = (accessed_before, accessed_after)
+        && before <= after
+    {
+        bail!(
+            "--accessed-after must be longer than --accessed-before, otherwise no project can match"
+        );
+    }
 
-        // spawn cargo clean and eats the output
-        if let Ok(out) = Command::new("cargo")
-            .arg("clean")
-            .current_dir(entry.path())
-            .output()
-        {
-            if !out.status.success() {
-                continue;
+    // measuring only is a dry run that doesn't even ask cargo
+    let dry_run = cmd.dry_run || cmd.stats_only;
+    // cargo runs inside each project, a relative path to it is meant from here
+    let cargo = resolve_cargo(
+        cmd.cargo_path,
+        &current_dir().context("Failed to get the current directory to find --cargo-path in")?,
+    );
+    let options = ScanOptions {
+        before,
+        after,
+        rules,
+        accessed_before,
+        // applied in the scan, where --rule picks each project's bound
+        older_than_accessed: cmd.older_than_accessed,
+        accessed_after,
+        skip_without_mtime: cmd.skip_without_mtime,
+        git_commit_time: cmd.git_commit_time,
+        age_field: cmd.age_field,
+        deep_mtime: cmd.deep_mtime,
+        filter: {
+            let filter = PathFilter::new(&cmd.include, &cmd.exclude)?;
+            match &cmd.pattern_file {
+                Some(path) => filter.with_pattern_file(path)?,
+                None => filter,
             }
-
-            let out = String::from_utf8_lossy(&out.stderr);
-            if out.contains("Removed 0 files") {
-                continue;
+        },
+        no_nested: cmd.no_nested,
+        exclude_workspace_members: cmd.exclude_workspace_members,
+        parallel_walk: cmd.parallel_walk,
+        skip_submodules: cmd.skip_submodules,
+        single_project,
+        target_names: cmd.target_dir_name.clone(),
+        target_env: target_env.clone(),
+        exclude_names: cmd.exclude_name,
+        name: cmd.name,
+        incremental_only: cmd.incremental_only,
+        stats_only: cmd.stats_only,
+        scan_only: cmd.scan_only,
+        size_cache: cmd
+            .stats_only
+            .then(|| SizeCache::load(cmd.no_cache))
+            .flatten(),
+        // listing, measuring and removing incremental caches don't need a working cargo
+        cargo_version: if cmd.scan_only || cmd.stats_only || cmd.incremental_only {
+            String::new()
+        } else {
+            check_cargo(&cargo)?
+        },
+        clean: CleanOptions {
+            cargo,
+            retries: cmd.retries,
+            build_jobs: cmd.threads_per_project,
+            packages: cmd.package,
+            target: cmd.target,
+            dry_run,
+            assume_freed: cmd.assume_freed,
+        },
+        prune_empty_target: cmd.prune_empty_target,
+        delete_lockfile: cmd.delete_lockfile,
+        fail_fast: cmd.fail_fast,
+        interrupted: Arc::default(),
+        jobs: cmd.jobs,
+        io_parallelism: cmd.io_parallelism,
+        progress_fd: cmd.progress_fd.and_then(|fd| match open_progress_fd(fd) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!(
+                    "{:>12} not reporting progress on fd {fd}: {e}",
+                    style("Warning").bold().yellow().for_stderr()
+                );
+                None
             }
+        }),
+        sink,
+        format: cmd.format,
+        size_format,
+        no_target_required: cmd.no_target_required,
+        list_already_clean: cmd.verbose,
+        keep_latest: cmd.keep_latest,
+        min_size: cmd.min_size.as_deref().map(parse_size).transpose()?,
+        fail_on_size: cmd.fail_on_size.as_deref().map(parse_size).transpose()?,
+        verify: cmd.verify.as_deref().map(parse_size).transpose()?,
+        free_at_least: cmd.free_at_least.as_deref().map(parse_size).transpose()?,
+        sort: cmd.sort,
+        stable_order: cmd.stable_order,
+        label: cmd.label,
+        min_projects: cmd.min_projects,
+        by_profile: cmd.by_profile,
+        timings: cmd.timings,
+        exec: cmd.exec,
+    };
 
-            cleaned_projects += 1;
-            let caps = re.captures(&out).expect("Failed to parse cargo clean output");
-            let files = caps["files"].parse::<usize>()?;
-            let size = caps["size"].parse::<f64>()?;
-            let unit = &caps["unit"];
-            removed_size += match unit {
-                "KiB" => size,
-                "MiB" => size * 1024.,
-                "GiB" => size * 1024. * 1024.,
-                _ => unreachable!("Unknown unit"),
-            };
-            removed_files += files;
-
-            pb.println(format!(
-                "{:>12} {} files, {}{} total in {}",
-                style("Removed").bold().green(),
-                files,
-                size,
-                unit,
-                entry.path().display()
+    let baseline = cmd.baseline.as_deref().map(Report::load).transpose()?;
+    let when_below = cmd.when_below.as_deref().map(parse_size).transpose()?;
+    let when_used_above = cmd
+        .when_used_above
+        .as_deref()
+        .map(parse_percent)
+        .transpose()?;
+
+    // the terminal stays free for the report unless it goes to a file
+    let ui = if cmd.output_file.is_none() && (cmd.format == OutputFormat::Ndjson || cmd.total_only)
+    {
+        Ui::Silent
+    } else if cmd.report_only_failures {
+        Ui::FailuresOnly
+    } else if cmd.no_progress || !stderr().is_terminal() || is_dumb_terminal() {
+        Ui::Plain
+    } else {
+        Ui::progress(setup_progress_bar()?)
+    };
+    let ui = match &cmd.output {
+        Some(path) => {
+            Ui::tee(ui, path).with_context(|| format!("Failed to create {}", path.display()))?
+        }
+        None => ui,
+    };
+    // a disk with room to spare needs no cleaning
+    if when_below.is_some() || when_used_above.is_some() {
+        let available = fs4::available_space(&dir)
+            .with_context(|| format!("Failed to read the free space of {}", dir.display()))?;
+        let total = fs4::total_space(&dir)
+            .with_context(|| format!("Failed to read the size of {}", dir.display()))?;
+        let used = 100. * (1. - available as f64 / total.max(1) as f64);
+        let low = when_below.is_some_and(|min| available < min);
+        let full = when_used_above.is_some_and(|max| used > max);
+        if !low && !full {
+            ui.println(format!(
+                "{:>12} cleaning, {} available and {used:.0}% used on {}",
+                style("Skipped").bold().green(),
+                size_format.format(available as f64 / 1024.),
+                dir.display()
             ));
+            return Ok(());
+        }
+    }
+    let free_before = if cmd.real_freed {
+        Some(
+            fs4::free_space(&dir)
+                .with_context(|| format!("Failed to read the free space of {}", dir.display()))?,
+        )
+    } else {
+        None
+    };
+    // a second Ctrl-C gives up on the cleans still running
+    let interrupted = options.interrupted.clone();
+    ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::Relaxed) {
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    })
+    .context("Failed to set the Ctrl-C handler")?;
+    let mut stats = process_directory(&dir, &options, &ui)?;
+    if options.scan_only {
+        // the paths went to stdout, why the rest was left out helps debugging filters
+        if cmd.verbose {
+            ui.println(format!("{:>12} {}", "Skipped", stats.skipped));
+        }
+        ui.clear();
+        exit_if_interrupted(&stats);
+        return check_oversized(&stats, options.fail_on_size);
+    }
+    if let Some(cache) = &options.size_cache
+        && let Err(e) = cache.save()
+    {
+        ui.warn(format!(
+            "{:>12} failed to save the size cache: {e:#}",
+            style("Warning").bold().yellow()
+        ));
+    }
+
+    let sccache = cmd
+        .sccache
+        .then(|| sccache_dir().context("Failed to locate the sccache directory"))
+        .transpose()?;
+    let home = cmd
+        .include_cargo_cache
+        .then(|| cargo_home().context("Failed to locate the cargo home directory"))
+        .transpose()?;
+    let caches = SharedCaches {
+        sccache: sccache.as_deref(),
+        cargo_home: home.as_deref(),
+        keep_downloads: cmd.keep_downloads,
+        yes: cmd.yes,
+    };
+    caches.clear(&options, &mut stats, &ui)?;
+
+    if let Some(free_before) = free_before {
+        let free_after = fs4::free_space(&dir)
+            .with_context(|| format!("Failed to read the free space of {}", dir.display()))?;
+        stats.disk_freed_kib = Some((free_after as f64 - free_before as f64) / 1024.);
+    }
+
+    let summary = Summary::new(&stats, started.elapsed());
+    if let Some(path) = &cmd.summary_file {
+        summary.write_atomic(path)?;
+    }
+    let lifetime = match &cmd.stats_file {
+        Some(path) if !dry_run => {
+            let mut lifetime = Lifetime::load(path).unwrap_or_else(|e| {
+                ui.warn(format!(
+                    "{:>12} starting the lifetime stats afresh: {e:#}",
+                    style("Warning").bold().yellow()
+                ));
+                Lifetime::default()
+            });
+            lifetime.add(&summary);
+            lifetime.write_atomic(path)?;
+            Some(lifetime)
+        }
+        _ => None,
+    };
+
+    let mut report = Report::from(&stats);
+    if let Some(baseline) = &baseline {
+        for line in report.diff(baseline, size_format) {
+            ui.println(line.to_string());
         }
     }
+    match cmd.format {
+        OutputFormat::Human => match summary_format {
+            SummaryFormat::Human => {}
+            SummaryFormat::Json => options.sink.write_line(&serde_json::to_string(&summary)?)?,
+            SummaryFormat::Csv => options.sink.write_line(&summary.to_csv())?,
+        },
+        OutputFormat::Json | OutputFormat::JsonPretty => {
+            report.summary = summary_on_stdout.then(|| summary.clone());
+            let pretty = cmd.format == OutputFormat::JsonPretty;
+            options.sink.write_line(&report.to_json(pretty)?)?;
+        }
+        OutputFormat::Ndjson if summary_on_stdout => {
+            Event::Summary(&summary).emit(&options.sink)?
+        }
+        OutputFormat::Ndjson => {}
+    }
+
+    if cmd.total_only {
+        // what couldn't be cleaned still belongs on the line
+        options
+            .sink
+            .write_line(&format!("{}{}", stats.totals(), stats.failures()))?;
+        exit_if_interrupted(&stats);
+        check_permissions(&stats, cmd.fail_on_permission_denied)?;
+        check_failures(&stats, cmd.report_only_failures)?;
+        return check_oversized(&stats, options.fail_on_size);
+    }
 
-    let removed_size = if removed_size > 1024. {
-        format!("{:.2}MiB", removed_size / 1024.)
-    } else if removed_size > 1024. * 1024. {
-        format!("{:.2}GiB", removed_size / 1024. / 1024.)
+    let mut summary = match &cmd.summary_template {
+        Some(template) => template.render(&summary, size_format),
+        None => stats.to_string(),
+    };
+    for (profile, size_kib) in &stats.profiles {
+        summary.push_str(&format!(
+            "\n{:>12}   {profile}: {}",
+            "",
+            size_format.format(*size_kib)
+        ));
+    }
+    if let Some(depth) = cmd.group_by {
+        for (group, size_kib) in stats.grouped(&dir, depth) {
+            // projects directly in the scanned directory
+            let group = if group.as_os_str().is_empty() {
+                &dir
+            } else {
+                &group
+            };
+            summary.push_str(&format!(
+                "\n{:>12}   {}: {}",
+                "",
+                group.display(),
+                size_format.format(size_kib)
+            ));
+        }
+    }
+    for (i, path) in stats.permission_denied.iter().enumerate() {
+        summary.push_str(&format!(
+            "\n{:>12} {}",
+            if i == 0 { "Denied" } else { "" },
+            path.display()
+        ));
+    }
+    for (i, record) in stats.slowest(SLOWEST_SHOWN).iter().enumerate() {
+        summary.push_str(&format!(
+            "\n{:>12} {:.2}s {}",
+            if i == 0 { "Slowest" } else { "" },
+            record.elapsed_secs.unwrap_or_default(),
+            record.path.display()
+        ));
+    }
+    if let Some(lifetime) = &lifetime {
+        summary.push_str(&format!(
+            "\n{:>12} {} in {} runs",
+            "Lifetime",
+            size_format.format(lifetime.size_kib),
+            lifetime.runs
+        ));
+    }
+    if cmd.verbose {
+        summary.push_str(&format!("\n{:>12} {}", "Skipped", stats.skipped));
+    }
+    if cmd.format == OutputFormat::Human && summary_on_stdout && cmd.output_file.is_none() {
+        ui.clear();
     } else {
-        format!("{:.2}KiB", removed_size)
+        ui.finish(if dry_run { "Reclaimable" } else { "Cleaned" }, summary);
+    }
+    if let Some(path) = &cmd.output {
+        ui.flush()
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+
+    exit_if_interrupted(&stats);
+    check_permissions(&stats, cmd.fail_on_permission_denied)?;
+    check_failures(&stats, cmd.report_only_failures)?;
+    check_oversized(&stats, options.fail_on_size)
+}
+
+/// The caches outside any project that `--sccache` and `--include-cargo-cache` clear
+/// once the projects are done.
+struct SharedCaches<'a> {
+    sccache: Option<&'a Path>,
+    cargo_home: Option<&'a Path>,
+    /// Leave the downloaded `.crate` files in the cargo home alone.
+    keep_downloads: bool,
+    /// Remove cargo's caches without asking.
+    yes: bool,
+}
+
+impl SharedCaches<'_> {
+    /// Clear the caches and add what went to `stats`. Nothing is touched after Ctrl-C,
+    /// the partial summary is due then.
+    fn clear(
+        &self,
+        options: &ScanOptions,
+        stats: &mut CleanupStats,
+        ui: &Ui,
+    ) -> anyhow::Result<()> {
+        if stats.interrupted {
+            return Ok(());
+        }
+        let dry_run = options.clean.dry_run;
+        if let Some(sccache) = self.sccache
+            && sccache.is_dir()
+        {
+            ui.status("Clearing", sccache.display());
+            let size = clear_dir_contents(sccache, dry_run)
+                .with_context(|| format!("Failed to clear {}", sccache.display()))?;
+            ui.println(format!(
+                "{:>12} {} files, {} total in {}",
+                style(options.removed_label()).bold().green(),
+                size.files,
+                options.size_format.format(size.size_kib()),
+                sccache.display()
+            ));
+            stats.sccache = Some(CleanOutput {
+                files: size.files,
+                size_kib: size.size_kib(),
+                ..Default::default()
+            });
+        }
+
+        if let Some(home) = self.cargo_home {
+            ui.status("Scanning", home.display());
+            let entries = old_cargo_cache_entries(home, options.before, self.keep_downloads);
+            let size = entries_size(&entries);
+            if size.files > 0 {
+                let description = format!(
+                    "{} cached downloads and checkouts, {} files, {} total in {}",
+                    entries.len(),
+                    size.files,
+                    options.size_format.format(size.size_kib()),
+                    home.display()
+                );
+                // removed downloads cost network traffic to get back, sources only re-extracting,
+                // so what each directory contributes is shown before asking
+                let breakdown: Vec<_> = cargo_cache_breakdown(home, &entries)
+                    .into_iter()
+                    .map(|(dir, size)| {
+                        format!("{dir}: {}", options.size_format.format(size.size_kib()))
+                    })
+                    .collect();
+                let breakdown = format!("{:>12}   {}", "", breakdown.join(", "));
+                let remove = !dry_run
+                    && (self.yes
+                        || confirm(ui, &format!("{description}\n{breakdown}\nRemove them?"))?);
+                // Ctrl-C while the question was open answers it with no
+                if options.interrupted.load(Ordering::Relaxed) {
+                    stats.interrupted = true;
+                    return Ok(());
+                }
+                if remove {
+                    ui.status("Clearing", home.display());
+                    remove_entries(&entries).with_context(|| {
+                        format!("Failed to clear the cargo cache in {}", home.display())
+                    })?;
+                }
+                if remove || dry_run {
+                    ui.println(format!(
+                        "{:>12} {description}",
+                        style(options.removed_label()).bold().green(),
+                    ));
+                    stats.cargo_cache = Some(CleanOutput {
+                        files: size.files,
+                        size_kib: size.size_kib(),
+                        ..Default::default()
+                    });
+                } else {
+                    ui.warn(format!(
+                        "{:>12} {description}, pass --yes to remove them without asking",
+                        style("Kept").bold().yellow(),
+                    ));
+                }
+                ui.println(breakdown);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stands in for a bound reaching back before the epoch, later than any timestamp a
+/// project could have.
+const FAR_FUTURE: Duration = Duration::from_secs(1000 * 365 * 24 * 3600);
+
+/// The instant `age` before `now`, `None` when that falls before the epoch (or further
+/// back than `SystemTime` can hold), where no real timestamp is.
+fn time_ago(now: SystemTime, age: Duration) -> Option<SystemTime> {
+    now.checked_sub(age).filter(|time| *time >= UNIX_EPOCH)
+}
+
+/// `age` before `now` as the latest timestamp a project may have. An age reaching back
+/// before the epoch puts the bound in the far future instead, so that every project
+/// counts as old enough whatever its timestamp, and `what` is warned about.
+fn before_bound(now: SystemTime, age: Duration, what: &str) -> SystemTime {
+    time_ago(now, age).unwrap_or_else(|| {
+        eprintln!(
+            "{:>12} {what} reaches back before 1970, cleaning every project whatever its timestamp",
+            style("Warning").bold().yellow().for_stderr()
+        );
+        now + FAR_FUTURE
+    })
+}
+
+/// `age` before `now` as the earliest timestamp a project may have, the epoch when the age
+/// reaches back before it, which leaves no project out and is warned about as `what`.
+fn after_bound(now: SystemTime, age: Duration, what: &str) -> SystemTime {
+    time_ago(now, age).unwrap_or_else(|| {
+        eprintln!(
+            "{:>12} {what} reaches back before 1970, no project is too old for it",
+            style("Warning").bold().yellow().for_stderr()
+        );
+        UNIX_EPOCH
+    })
+}
+
+/// `TERM=dumb`, set by editors' embedded shells and some CI runners, can't redraw a spinner.
+fn is_dumb_terminal() -> bool {
+    std::env::var_os("TERM").is_some_and(|term| term == "dumb")
+}
+
+/// After Ctrl-C, exit once the partial summary is out, telling scripts the run didn't finish.
+fn exit_if_interrupted(stats: &CleanupStats) {
+    if stats.interrupted {
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+}
+
+/// Fail with `--fail-on-permission-denied` when some targets couldn't be cleaned for lack
+/// of permissions, listing them.
+fn check_permissions(stats: &CleanupStats, fail: bool) -> anyhow::Result<()> {
+    if !fail || stats.permission_denied.is_empty() {
+        return Ok(());
+    }
+    let projects: Vec<_> = stats
+        .permission_denied
+        .iter()
+        .map(|path| format!("  {}", path.display()))
+        .collect();
+    bail!(
+        "Permission denied cleaning {} projects:\n{}",
+        projects.len(),
+        projects.join("\n")
+    )
+}
+
+/// Fail with `--report-only-failures` when some projects couldn't be cleaned, for lack of
+/// permissions or otherwise.
+fn check_failures(stats: &CleanupStats, fail: bool) -> anyhow::Result<()> {
+    let failed = stats.failed + stats.permission_denied.len();
+    if !fail || failed == 0 {
+        return Ok(());
+    }
+    bail!(
+        "{failed} of {} projects failed to clean",
+        stats.projects + failed
+    )
+}
+
+/// Fail when `--fail-on-size` found targets above the limit, listing them.
+fn check_oversized(stats: &CleanupStats, limit: Option<u64>) -> anyhow::Result<()> {
+    let Some(limit) = limit.filter(|_| !stats.oversized.is_empty()) else {
+        return Ok(());
     };
+    let projects: Vec<_> = stats
+        .oversized
+        .iter()
+        .map(|(path, bytes)| {
+            format!(
+                "  {}: {}",
+                path.display(),
+                stats.format_size(*bytes as f64 / 1024.)
+            )
+        })
+        .collect();
+    bail!(
+        "{} projects have a target larger than {}:\n{}",
+        projects.len(),
+        stats.format_size(limit as f64 / 1024.),
+        projects.join("\n")
+    )
+}
 
-    pb.set_prefix("Cleaned");
-    pb.finish_with_message(format!(
-        "{cleaned_projects} projects, {removed_files} files, {removed_size} total",
-    ));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
 
-    Ok(())
+    #[test]
+    fn ages_before_the_epoch_take_every_project() {
+        let now = SystemTime::now();
+        let huge = TimeParser::parse_duration("500000000000y").unwrap();
+        assert_eq!(time_ago(now, huge), None);
+        assert_eq!(time_ago(now, Duration::MAX), None);
+        // representable, but still before any timestamp
+        let ancient = TimeParser::parse_duration("9999y").unwrap();
+        assert_eq!(time_ago(now, ancient), None);
+        assert_eq!(
+            time_ago(now, Duration::from_secs(60)),
+            Some(now - Duration::from_secs(60))
+        );
+
+        for age in [huge, ancient] {
+            assert!(before_bound(now, age, "--past") > now);
+            assert_eq!(after_bound(now, age, "--newer-than"), UNIX_EPOCH);
+        }
+        let before = before_bound(now, Duration::from_secs(60), "--past");
+        assert_eq!(before, now - Duration::from_secs(60));
+    }
+
+    #[test]
+    fn denied_cleans_count_as_failures() {
+        let mut stats = CleanupStats {
+            projects: 3,
+            ..Default::default()
+        };
+        assert!(check_failures(&stats, true).is_ok());
+        stats.permission_denied.push(PathBuf::from("/src/theirs"));
+        assert!(check_failures(&stats, false).is_ok());
+        let e = check_failures(&stats, true).unwrap_err();
+        assert_eq!(e.to_string(), "1 of 4 projects failed to clean");
+        stats.failed = 1;
+        let e = check_failures(&stats, true).unwrap_err();
+        assert_eq!(e.to_string(), "2 of 5 projects failed to clean");
+    }
+
+    #[test]
+    fn shared_caches_are_left_alone_after_ctrl_c() {
+        let dir = tempfile::tempdir().unwrap();
+        let sccache = dir.path().join("sccache");
+        fs::create_dir_all(sccache.join("a")).unwrap();
+        fs::write(sccache.join("a/object"), [0u8; 512]).unwrap();
+        let home = dir.path().join("cargo");
+        let src = home.join("registry/src/index/serde-1.0.0");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("lib.rs"), [0u8; 512]).unwrap();
+        let caches = SharedCaches {
+            sccache: Some(&sccache),
+            cargo_home: Some(&home),
+            keep_downloads: false,
+            yes: true,
+        };
+        let options = ScanOptions::default();
+
+        let mut stats = CleanupStats {
+            interrupted: true,
+            ..Default::default()
+        };
+        caches.clear(&options, &mut stats, &Ui::Silent).unwrap();
+        assert!(sccache.join("a/object").exists());
+        assert!(src.exists());
+        assert!(stats.sccache.is_none() && stats.cargo_cache.is_none());
+
+        stats.interrupted = false;
+        caches.clear(&options, &mut stats, &Ui::Silent).unwrap();
+        assert!(!sccache.join("a").exists());
+        assert!(!src.exists());
+        assert_eq!(stats.sccache.unwrap().files, 1);
+        assert_eq!(stats.cargo_cache.unwrap().files, 1);
+
+        // pressed once the projects were done, e.g. at the question
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("lib.rs"), [0u8; 512]).unwrap();
+        let options = ScanOptions::default();
+        options.interrupted.store(true, Ordering::Relaxed);
+        let mut stats = CleanupStats::default();
+        caches.clear(&options, &mut stats, &Ui::Silent).unwrap();
+        assert!(src.exists());
+        assert!(stats.interrupted);
+        assert!(stats.cargo_cache.is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn root_and_home_are_wide_scans() {
+        assert!(is_wide_scan(Path::new("/")));
+        assert!(is_wide_scan(&dirs::home_dir().unwrap()));
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!is_wide_scan(dir.path()));
+    }
 }
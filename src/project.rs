@@ -0,0 +1,1099 @@
+use crate::caches::cargo_home;
+use crate::error::{CargoStderr, CleanError, is_permission_error};
+use crate::size::dir_size;
+use anyhow::{Context, bail};
+use globset::GlobBuilder;
+use regex::Regex;
+use std::cell::OnceCell;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::LazyLock;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use walkdir::WalkDir;
+
+// cargo clean output format is: \s+ Removed \d+ files?, \d+(.\d+)? B/KiB/MiB/GiB total
+// with `--dry-run` the verb is `Summary` instead
+static REMOVED_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?:Removed|Summary) (?P<files>\d+) files?(?:, (?P<size>\d+(?:\.\d+)?)(?P<unit>\w+) total)?",
+    )
+    .expect("Invalid regex")
+});
+
+/// Marker file opting a project and everything below it out of cleaning.
+const IGNORE_FILE: &str = ".cargocleanignore";
+
+/// A directory containing a `Cargo.toml`.
+pub struct CargoProject {
+    path: PathBuf,
+    target_env: TargetEnv,
+    /// Names the target directory may have below the project instead of `target`.
+    target_names: Vec<String>,
+    target_dir: OnceCell<PathBuf>,
+    manifest: OnceCell<Option<toml::Table>>,
+}
+
+// stderr fragments of failures that may go away on their own: another process holding
+// files in target/, or a hiccup of a network filesystem
+const TRANSIENT_ERRORS: &[&str] = &[
+    "Permission denied",
+    "Access is denied",
+    "being used by another process",
+    "Resource busy",
+    "Directory not empty",
+    "Input/output error",
+    "Stale file handle",
+    "Resource temporarily unavailable",
+    "timed out",
+    "os error 5)",
+    "os error 11)",
+    "os error 13)",
+    "os error 16)",
+    "os error 32)",
+    "os error 116)",
+];
+
+/// Base delay before retrying a failed clean, doubled after every attempt.
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// How `cargo clean` should be invoked.
+#[derive(Debug, Clone, Default)]
+pub struct CleanOptions {
+    /// The cargo binary, either a path or a name looked up in `PATH`.
+    pub cargo: PathBuf,
+    /// Number of extra attempts when cargo fails because of a locked target or an IO hiccup.
+    pub retries: u32,
+    /// Passed to cargo as `CARGO_BUILD_JOBS` to bound its own parallelism.
+    pub build_jobs: Option<usize>,
+    /// Packages forwarded as `cargo clean -p <NAME>`, all of them when empty.
+    pub packages: Vec<String>,
+    /// Only clean the artifacts built for this target triple.
+    pub target: Option<String>,
+    /// Only report what would be removed.
+    pub dry_run: bool,
+    /// Measure the target before running cargo and credit that as freed, instead of
+    /// parsing what cargo reports.
+    pub assume_freed: bool,
+}
+
+/// The environment cargo consults to find a project's target directory, empty unless
+/// read with `TargetEnv::from_env` so that nothing exported around the process leaks in.
+#[derive(Debug, Clone, Default)]
+pub struct TargetEnv {
+    /// `CARGO_TARGET_DIR`, overriding any configuration.
+    pub target_dir: Option<PathBuf>,
+    /// The cargo home, whose `config.toml` is read after those up the directory tree.
+    pub cargo_home: Option<PathBuf>,
+}
+
+impl TargetEnv {
+    pub fn from_env() -> Self {
+        Self {
+            target_dir: std::env::var_os("CARGO_TARGET_DIR").map(PathBuf::from),
+            cargo_home: cargo_home(),
+        }
+    }
+}
+
+/// How a `Cargo.toml` relates to others, see `CargoProject::kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestKind {
+    /// A package outside any workspace.
+    Standalone,
+    /// A manifest with a `[workspace]`, owning the shared target directory.
+    WorkspaceRoot,
+    /// A package building into its workspace's target directory.
+    Member,
+}
+
+/// What a single `cargo clean` reported to have removed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CleanOutput {
+    pub files: usize,
+    pub size_kib: f64,
+    /// How many retries were needed before the clean went through.
+    pub retries: u32,
+    /// Whether the size was measured on disk rather than reported by cargo.
+    pub measured: bool,
+}
+
+impl CargoProject {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            target_env: TargetEnv::default(),
+            target_names: Vec::new(),
+            target_dir: OnceCell::new(),
+            manifest: OnceCell::new(),
+        }
+    }
+
+    /// Resolve the target directory against `env` instead of as if nothing was set.
+    pub fn with_target_env(mut self, env: &TargetEnv) -> Self {
+        self.target_env = env.clone();
+        self
+    }
+
+    /// Look for a target directory with one of `names`, in that order, instead of
+    /// `target`. Cargo is then pointed at it with `--target-dir`.
+    pub fn with_target_names(mut self, names: &[String]) -> Self {
+        self.target_names = names.to_vec();
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The directory cargo builds into for this project.
+    ///
+    /// Resolved like cargo does, see `resolve_target_dir`, falling back to `./target` or
+    /// the first existing directory named after `with_target_names`.
+    pub fn target_dir(&self) -> PathBuf {
+        self.target_dir
+            .get_or_init(|| {
+                resolve_target_dir(
+                    &self.path,
+                    self.target_env.target_dir.as_deref(),
+                    self.target_env.cargo_home.as_deref(),
+                )
+                .unwrap_or_else(|| self.default_target_dir())
+            })
+            .clone()
+    }
+
+    fn default_target_dir(&self) -> PathBuf {
+        let Some(first) = self.target_names.first() else {
+            return self.path.join("target");
+        };
+        self.target_names
+            .iter()
+            .map(|name| self.path.join(name))
+            .find(|dir| dir.is_dir())
+            .unwrap_or_else(|| self.path.join(first))
+    }
+
+    /// Canonical path of the target directory, identifying it even when it is shared
+    /// through symlinks or reached via different paths.
+    pub fn target_key(&self) -> PathBuf {
+        let target = self.target_dir();
+        target
+            .canonicalize()
+            .or_else(|_| self.path.canonicalize().map(|p| p.join("target")))
+            .unwrap_or(target)
+    }
+
+    /// A project is worth cleaning if it has a manifest and, when `require_target`
+    /// is set, a target directory. A symlinked target counts if it leads to a
+    /// directory, a `target` file or dangling symlink doesn't.
+    ///
+    /// Returns an error when a `Cargo.toml` is present but can't be read, e.g.
+    /// because of its permissions or because it is a dangling symlink.
+    pub fn is_valid_project(&self, require_target: bool) -> io::Result<bool> {
+        let manifest = self.path.join("Cargo.toml");
+        match fs::symlink_metadata(&manifest) {
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+                ) =>
+            {
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        }
+        if require_target && !self.target_dir().is_dir() {
+            return Ok(false);
+        }
+        File::open(&manifest)?;
+        Ok(true)
+    }
+
+    /// Whether the project opted out of cleaning with a `.cargocleanignore` file.
+    pub fn is_ignored(&self) -> bool {
+        self.path.join(IGNORE_FILE).exists()
+    }
+
+    /// The parsed `Cargo.toml`, `None` when it can't be read or isn't valid TOML.
+    fn manifest(&self) -> Option<&toml::Table> {
+        self.manifest
+            .get_or_init(|| {
+                fs::read_to_string(self.path.join("Cargo.toml"))
+                    .ok()
+                    .and_then(|manifest| manifest.parse().ok())
+            })
+            .as_ref()
+    }
+
+    /// Whether the manifest declares a `[workspace]`.
+    pub fn is_workspace_root(&self) -> bool {
+        self.manifest()
+            .is_some_and(|manifest| manifest.contains_key("workspace"))
+    }
+
+    /// The `[package] name`, `None` for virtual workspace manifests.
+    pub fn package_name(&self) -> Option<&str> {
+        self.manifest()?.get("package")?.get("name")?.as_str()
+    }
+
+    /// The package name and, for workspaces, those of the members.
+    pub fn package_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.package_name().map(str::to_owned).into_iter().collect();
+        for dir in self.member_dirs() {
+            if let Some(name) = CargoProject::new(dir).package_name() {
+                names.push(name.to_owned());
+            }
+        }
+        names
+    }
+
+    /// Directories of the workspace members besides the root itself, found by expanding
+    /// `workspace.members` like cargo does and leaving out `workspace.exclude`.
+    fn member_dirs(&self) -> Vec<PathBuf> {
+        let Some(workspace) = self
+            .manifest()
+            .and_then(|manifest| manifest.get("workspace"))
+        else {
+            return Vec::new();
+        };
+        let list = |key: &str| -> Vec<&str> {
+            workspace
+                .get(key)
+                .and_then(|list| list.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.as_str())
+                .collect()
+        };
+        let excluded: Vec<PathBuf> = list("exclude")
+            .iter()
+            .map(|dir| normalize(&self.path.join(dir)))
+            .collect();
+        list("members")
+            .iter()
+            .flat_map(|pattern| self.expand_member(pattern))
+            .map(|dir| normalize(&dir))
+            .filter(|dir| *dir != normalize(&self.path) && !excluded.contains(dir))
+            .collect()
+    }
+
+    /// Whether the project is a crate of its own, a workspace root or a member of a
+    /// workspace further up.
+    ///
+    /// Like cargo, a member either names its root in `package.workspace` or is listed in
+    /// the `workspace.members` of the closest manifest up the tree that has a `[workspace]`.
+    pub fn kind(&self) -> ManifestKind {
+        if self.is_workspace_root() {
+            return ManifestKind::WorkspaceRoot;
+        }
+        let Some(package) = self.manifest().and_then(|manifest| manifest.get("package")) else {
+            return ManifestKind::Standalone;
+        };
+        if package.get("workspace").is_some_and(|root| root.is_str()) {
+            return ManifestKind::Member;
+        }
+        let path = normalize(&self.path);
+        let root = self
+            .path
+            .ancestors()
+            .skip(1)
+            .map(CargoProject::new)
+            .find(CargoProject::is_workspace_root);
+        match root {
+            Some(root) if root.member_dirs().contains(&path) => ManifestKind::Member,
+            _ => ManifestKind::Standalone,
+        }
+    }
+
+    /// Directories matched by a `workspace.members` entry, which may be a glob like `crates/*`.
+    fn expand_member(&self, pattern: &str) -> Vec<PathBuf> {
+        let pattern = pattern.trim_start_matches("./");
+        if !pattern.contains(['*', '?', '[']) {
+            return vec![self.path.join(pattern)];
+        }
+        let Ok(glob) = GlobBuilder::new(pattern).literal_separator(true).build() else {
+            return Vec::new();
+        };
+        let glob = glob.compile_matcher();
+        WalkDir::new(&self.path)
+            .min_depth(1)
+            .max_depth(Path::new(pattern).components().count())
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_dir())
+            .filter(|e| {
+                e.path()
+                    .strip_prefix(&self.path)
+                    .is_ok_and(|dir| glob.is_match(dir))
+            })
+            .map(walkdir::DirEntry::into_path)
+            .collect()
+    }
+
+    /// Run `cargo clean` in the project, eating the output.
+    ///
+    /// Failures that look transient, a file in `target/` being locked by another
+    /// process or an IO error of a network filesystem, are retried up to
+    /// `options.retries` times with a growing delay. Anything else, like a broken
+    /// manifest, fails right away.
+    /// Returns `Ok(None)` if there was nothing to remove and an error if cargo failed.
+    /// With `options.packages` set, a workspace without any of them has nothing to remove.
+    pub fn clean(&self, options: &CleanOptions) -> Result<Option<CleanOutput>, CleanError> {
+        // without `-p`, cargo ignores `--target` and removes the whole target directory
+        if let Some(triple) = &options.target
+            && options.packages.is_empty()
+        {
+            let dir = self.target_dir().join(triple);
+            let dirs = dir.is_dir().then_some(dir);
+            return Ok(remove_dirs(dirs, options.dry_run)?);
+        }
+
+        let target_dir = (!self.target_names.is_empty()).then(|| self.target_dir());
+        let size_before = options.assume_freed.then(|| dir_size(&self.target_dir()));
+        let mut attempt = 0;
+        let stderr = loop {
+            let mut cargo = Command::new(&options.cargo);
+            // outside the terminal's process group cargo doesn't see Ctrl-C, which only
+            // stops the scan from starting further cleans
+            #[cfg(unix)]
+            std::os::unix::process::CommandExt::process_group(&mut cargo, 0);
+            let out = cargo
+                .arg("clean")
+                .args(
+                    target_dir
+                        .iter()
+                        .flat_map(|dir| ["--target-dir".as_ref(), dir.as_os_str()]),
+                )
+                .args(options.packages.iter().flat_map(|p| ["-p", p]))
+                .args(options.target.iter().flat_map(|t| ["--target", t]))
+                .args(options.dry_run.then_some("--dry-run"))
+                .envs(
+                    options
+                        .build_jobs
+                        .map(|jobs| ("CARGO_BUILD_JOBS", jobs.to_string())),
+                )
+                // the output is parsed, keep it in English whatever the user's locale
+                .env("LC_ALL", "C")
+                .env("LANG", "C")
+                .current_dir(&self.path)
+                .output()?;
+            let stderr = String::from_utf8_lossy(&out.stderr).into_owned();
+            if out.status.success() {
+                break stderr;
+            }
+            // the requested package isn't a member of this workspace
+            if stderr.contains("did not match any packages") {
+                return Ok(None);
+            }
+            if !is_transient_error(&stderr) {
+                return Err(cargo_failed(
+                    format!("cargo clean failed: {}", summarize(&stderr)),
+                    &stderr,
+                ));
+            }
+            if attempt >= options.retries {
+                return Err(cargo_failed(
+                    format!(
+                        "cargo clean failed, {} retries exhausted: {}",
+                        options.retries,
+                        summarize(&stderr)
+                    ),
+                    &stderr,
+                ));
+            }
+            thread::sleep(RETRY_DELAY * 2u32.pow(attempt));
+            attempt += 1;
+        };
+
+        let mut out = match size_before {
+            Some(size) => CleanOutput {
+                files: size.files,
+                size_kib: size.size_kib(),
+                measured: true,
+                ..Default::default()
+            },
+            None => parse_clean_output(&stderr)?,
+        };
+        if out.files == 0 {
+            return Ok(None);
+        }
+        out.retries = attempt;
+        Ok(Some(out))
+    }
+
+    /// When the last git commit touching the project directory was made, `None` outside
+    /// a git repository, without commits or when git can't be run.
+    pub fn last_commit_time(&self) -> Option<SystemTime> {
+        let out = Command::new("git")
+            .args(["log", "-1", "--format=%ct", "--", "."])
+            .current_dir(&self.path)
+            .output()
+            .ok()?;
+        if !out.status.success() {
+            return None;
+        }
+        let secs = String::from_utf8_lossy(&out.stdout).trim().parse().ok()?;
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Run a shell command in the project directory, `{path}` is replaced by its quoted
+    /// absolute path. The command's output is captured so it doesn't garble the progress bar.
+    pub fn run_hook(&self, command: &str) -> io::Result<Output> {
+        let path = std::path::absolute(&self.path)?;
+        let path = path.to_string_lossy();
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell
+                .arg("/C")
+                .arg(command.replace("{path}", &format!("\"{path}\"")));
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell
+                .arg("-c")
+                .arg(command.replace("{path}", &format!("'{}'", path.replace('\'', r"'\''"))));
+            shell
+        };
+        shell.current_dir(&self.path).output()
+    }
+
+    /// Remove the target directory if it contains nothing but empty directories.
+    ///
+    /// Returns whether it was removed; a target holding any file, symlink or
+    /// other non-directory entry is left untouched.
+    pub fn prune_empty_target(&self) -> std::io::Result<bool> {
+        let target = self.target_dir();
+        if !target.is_dir() {
+            return Ok(false);
+        }
+        for entry in WalkDir::new(&target) {
+            if !entry?.file_type().is_dir() {
+                return Ok(false);
+            }
+        }
+        // `remove_dir` refuses non-empty directories, guarding against files
+        // appearing between the check above and the removal
+        for entry in WalkDir::new(&target).contents_first(true) {
+            fs::remove_dir(entry?.path())?;
+        }
+        Ok(true)
+    }
+
+    /// Whether the package only builds a library, going by a `[lib]` section or `src/lib.rs`
+    /// without any binary. Workspace roots are never libraries, their lockfile covers
+    /// every member.
+    pub fn is_library(&self) -> bool {
+        let Some(manifest) = self.manifest().filter(|_| !self.is_workspace_root()) else {
+            return false;
+        };
+        let has_lib = manifest.contains_key("lib") || self.path.join("src/lib.rs").is_file();
+        let has_bin = manifest.contains_key("bin")
+            || self.path.join("src/main.rs").is_file()
+            || self.path.join("src/bin").is_dir();
+        has_lib && !has_bin
+    }
+
+    /// Remove `Cargo.lock`, so that the next build resolves the dependencies afresh.
+    /// Returns whether there was one.
+    pub fn delete_lockfile(&self) -> io::Result<bool> {
+        match fs::remove_file(self.path.join("Cargo.lock")) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Remove only the `incremental` directories below the target directory.
+    ///
+    /// These live at `target/<profile>/incremental` or, when cross compiling,
+    /// `target/<triple>/<profile>/incremental`. Returns `Ok(None)` if there were none.
+    /// With `dry_run` they are only measured.
+    pub fn clean_incremental(&self, dry_run: bool) -> Result<Option<CleanOutput>, CleanError> {
+        let target = self.target_dir();
+        let dirs: Vec<_> = WalkDir::new(&target)
+            .min_depth(2)
+            .max_depth(3)
+            .into_iter()
+            .filter_entry(|e| e.file_type().is_dir())
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name() == "incremental")
+            .map(|e| e.into_path())
+            .collect();
+        Ok(remove_dirs(dirs, dry_run)?)
+    }
+}
+
+/// Remove whole directories, reporting what they contained.
+fn remove_dirs(
+    dirs: impl IntoIterator<Item = PathBuf>,
+    dry_run: bool,
+) -> io::Result<Option<CleanOutput>> {
+    let mut files = 0;
+    let mut bytes = 0;
+    for dir in dirs {
+        let size = dir_size(&dir);
+        if !dry_run {
+            fs::remove_dir_all(&dir)?;
+        }
+        files += size.files;
+        bytes += size.bytes;
+    }
+    if files == 0 {
+        return Ok(None);
+    }
+    Ok(Some(CleanOutput {
+        files,
+        size_kib: bytes as f64 / 1024.,
+        measured: true,
+        ..Default::default()
+    }))
+}
+
+fn parse_clean_output(out: &str) -> Result<CleanOutput, CleanError> {
+    let unparsable = || CleanError::ParseOutput(out.to_owned());
+    let caps = REMOVED_RE.captures(out).ok_or_else(unparsable)?;
+    let files = caps["files"].parse::<usize>().map_err(|_| unparsable())?;
+    // cargo omits the size when nothing was removed
+    let (Some(size), Some(unit)) = (caps.name("size"), caps.name("unit")) else {
+        return Ok(CleanOutput::default());
+    };
+    let size = size.as_str().parse::<f64>().map_err(|_| unparsable())?;
+    let size_kib = match unit.as_str() {
+        "B" => size / 1024.,
+        "KiB" => size,
+        "MiB" => size * 1024.,
+        "GiB" => size * 1024. * 1024.,
+        unit => {
+            return Err(CleanError::ParseOutput(format!(
+                "unknown unit `{unit}` in {out}"
+            )));
+        }
+    };
+    Ok(CleanOutput {
+        files,
+        size_kib,
+        ..Default::default()
+    })
+}
+
+/// `path` without `.` components or trailing separators, so member paths written either
+/// way compare equal.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+/// The target directory of the project at `path` as configured: `env_target_dir` (i.e.
+/// `CARGO_TARGET_DIR`) first, then `build.target-dir` from the closest `.cargo/config.toml`
+/// up the directory tree or in `cargo_home`. Relative paths are taken from the directory
+/// holding the configuration, the parent of `.cargo` or of the cargo home.
+fn resolve_target_dir(
+    path: &Path,
+    env_target_dir: Option<&Path>,
+    cargo_home: Option<&Path>,
+) -> Option<PathBuf> {
+    if let Some(dir) = env_target_dir {
+        return Some(path.join(dir));
+    }
+    let cargo_home = cargo_home.map(|home| (home, home.parent().unwrap_or(home)));
+    path.ancestors()
+        .map(|dir| (dir.join(".cargo"), dir))
+        .chain(cargo_home.map(|(home, base)| (home.to_path_buf(), base)))
+        .find_map(|(config_dir, base)| configured_target_dir(&config_dir, base))
+}
+
+/// `build.target-dir` from `config.toml` (or the legacy `config`) in `config_dir`,
+/// relative paths are resolved against `base`.
+fn configured_target_dir(config_dir: &Path, base: &Path) -> Option<PathBuf> {
+    ["config.toml", "config"].iter().find_map(|name| {
+        let config = fs::read_to_string(config_dir.join(name)).ok()?;
+        let config = config.parse::<toml::Table>().ok()?;
+        let target_dir = config.get("build")?.get("target-dir")?.as_str()?;
+        Some(base.join(target_dir))
+    })
+}
+
+/// `cargo` as it can be run from inside every project: a relative path with a separator,
+/// like `./fakecargo`, is taken from `cwd` rather than from each project's directory.
+/// A bare name is left to be looked up in `PATH`.
+pub fn resolve_cargo(cargo: PathBuf, cwd: &Path) -> PathBuf {
+    if cargo.is_relative() && cargo.components().count() > 1 {
+        cwd.join(cargo)
+    } else {
+        cargo
+    }
+}
+
+/// Make sure `cargo` can actually be run, returning its version line.
+pub fn check_cargo(cargo: &Path) -> anyhow::Result<String> {
+    let out = Command::new(cargo)
+        .arg("--version")
+        .output()
+        .with_context(|| {
+            format!(
+                "Failed to run `{}`, check --cargo-path or the CARGO environment variable",
+                cargo.display()
+            )
+        })?;
+    if !out.status.success() {
+        bail!(
+            "`{} --version` failed, check --cargo-path or the CARGO environment variable",
+            cargo.display()
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_owned())
+}
+
+/// An error whose message is `summary` and whose cause is cargo's full error output.
+fn cargo_failed(summary: String, stderr: &str) -> CleanError {
+    CleanError::CargoFailed {
+        summary,
+        stderr: CargoStderr(stderr.trim().to_owned()),
+    }
+}
+
+/// First line of cargo's error output, which is usually enough to tell what went wrong.
+pub fn summarize(stderr: &str) -> &str {
+    stderr
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .unwrap_or("no error output")
+}
+
+fn is_transient_error(stderr: &str) -> bool {
+    // a denied permission stays denied on unix, only Windows also reports files another
+    // process holds open that way
+    if cfg!(unix) && is_permission_error(stderr) {
+        return false;
+    }
+    TRANSIENT_ERRORS.iter().any(|e| stderr.contains(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_without_manifest_is_not_a_project() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("target")).unwrap();
+
+        let project = CargoProject::new(dir.path());
+        assert!(!project.is_valid_project(true).unwrap());
+        assert!(!project.is_valid_project(false).unwrap());
+    }
+
+    #[test]
+    fn readable_manifest_with_target_is_a_project() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "[package]").unwrap();
+
+        let project = CargoProject::new(dir.path());
+        assert!(!project.is_valid_project(true).unwrap());
+        fs::create_dir(dir.path().join("target")).unwrap();
+        assert!(project.is_valid_project(true).unwrap());
+    }
+
+    #[test]
+    fn target_file_is_not_a_target() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "[package]").unwrap();
+        fs::write(dir.path().join("target"), "").unwrap();
+
+        let project = CargoProject::new(dir.path());
+        assert!(!project.is_valid_project(true).unwrap());
+        assert!(project.is_valid_project(false).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_target_must_lead_to_a_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "[package]").unwrap();
+        let shared = dir.path().join("shared-target");
+        std::os::unix::fs::symlink(&shared, dir.path().join("target")).unwrap();
+
+        assert!(
+            !CargoProject::new(dir.path())
+                .is_valid_project(true)
+                .unwrap()
+        );
+        fs::create_dir(&shared).unwrap();
+        assert!(
+            CargoProject::new(dir.path())
+                .is_valid_project(true)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn first_existing_target_name_is_used() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "[package]").unwrap();
+        let names = ["build".to_owned(), "out".to_owned()];
+
+        let project = CargoProject::new(dir.path()).with_target_names(&names);
+        assert!(!project.is_valid_project(true).unwrap());
+        fs::create_dir(dir.path().join("out")).unwrap();
+        let project = CargoProject::new(dir.path()).with_target_names(&names);
+        assert!(project.is_valid_project(true).unwrap());
+        assert_eq!(project.target_dir(), dir.path().join("out"));
+    }
+
+    #[test]
+    fn target_dir_from_cargo_config() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join(".cargo")).unwrap();
+        fs::write(
+            dir.path().join(".cargo/config.toml"),
+            "[build]\ntarget-dir = \"build-output\"",
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join("crate")).unwrap();
+
+        let project = CargoProject::new(dir.path().join("crate"));
+        assert_eq!(project.target_dir(), dir.path().join("build-output"));
+    }
+
+    #[test]
+    fn target_dir_env_and_cargo_home_are_resolved_like_cargo() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = dir.path().join("home/.cargo");
+        fs::create_dir_all(&home).unwrap();
+        fs::write(home.join("config.toml"), "[build]\ntarget-dir = \"shared\"").unwrap();
+        let project = dir.path().join("code/crate");
+        fs::create_dir_all(&project).unwrap();
+
+        assert_eq!(resolve_target_dir(&project, None, None), None);
+        // relative to the directory holding the cargo home, like `.cargo` up the tree
+        assert_eq!(
+            resolve_target_dir(&project, None, Some(&home)),
+            Some(dir.path().join("home/shared"))
+        );
+        assert_eq!(
+            resolve_target_dir(&project, Some(Path::new("out")), Some(&home)),
+            Some(project.join("out"))
+        );
+
+        fs::create_dir(dir.path().join("code/.cargo")).unwrap();
+        fs::write(
+            dir.path().join("code/.cargo/config.toml"),
+            "[build]\ntarget-dir = \"local\"",
+        )
+        .unwrap();
+        assert_eq!(
+            resolve_target_dir(&project, None, Some(&home)),
+            Some(dir.path().join("code/local"))
+        );
+
+        let env = TargetEnv {
+            target_dir: Some(PathBuf::from("/elsewhere")),
+            cargo_home: None,
+        };
+        let project = CargoProject::new(&project).with_target_env(&env);
+        assert_eq!(project.target_dir(), Path::new("/elsewhere"));
+    }
+
+    #[test]
+    fn clean_output_is_parsed() {
+        let out = parse_clean_output("     Removed 41 files, 16.8MiB total").unwrap();
+        assert_eq!((out.files, out.size_kib), (41, 16.8 * 1024.));
+        let out = parse_clean_output("     Removed 1 file, 512B total").unwrap();
+        assert_eq!((out.files, out.size_kib), (1, 0.5));
+        assert_eq!(parse_clean_output("     Removed 0 files").unwrap().files, 0);
+        assert!(matches!(
+            parse_clean_output("Cleaned up"),
+            Err(CleanError::ParseOutput(_))
+        ));
+    }
+
+    #[test]
+    fn last_commit_time_comes_from_git() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = CargoProject::new(dir.path());
+        assert_eq!(project.last_commit_time(), None);
+
+        fs::write(dir.path().join("Cargo.toml"), "[package]").unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(args)
+                .current_dir(dir.path())
+                .env("GIT_AUTHOR_NAME", "test")
+                .env("GIT_AUTHOR_EMAIL", "test@example.com")
+                .env("GIT_COMMITTER_NAME", "test")
+                .env("GIT_COMMITTER_EMAIL", "test@example.com")
+                .env("GIT_COMMITTER_DATE", "1600000000 +0000")
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?}");
+        };
+        git(&["init", "-q"]);
+        git(&["add", "Cargo.toml"]);
+        git(&["commit", "-q", "-m", "init"]);
+        assert_eq!(
+            project.last_commit_time(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000))
+        );
+    }
+
+    #[test]
+    fn package_name_from_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"demo\"").unwrap();
+        assert_eq!(CargoProject::new(dir.path()).package_name(), Some("demo"));
+
+        fs::write(dir.path().join("Cargo.toml"), "[workspace]").unwrap();
+        let project = CargoProject::new(dir.path());
+        assert_eq!(project.package_name(), None);
+        assert!(project.is_workspace_root());
+    }
+
+    #[test]
+    fn virtual_workspace_goes_by_its_member_names() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"cli\", \"crates/*\"]\nexclude = [\"crates/old\"]",
+        )
+        .unwrap();
+        for (member, name) in [
+            ("cli", "demo-cli"),
+            ("crates/core", "demo-core"),
+            ("crates/old", "old"),
+        ] {
+            fs::create_dir_all(dir.path().join(member)).unwrap();
+            fs::write(
+                dir.path().join(member).join("Cargo.toml"),
+                format!("[package]\nname = \"{name}\""),
+            )
+            .unwrap();
+        }
+        let mut names = CargoProject::new(dir.path()).package_names();
+        names.sort();
+        assert_eq!(names, ["demo-cli", "demo-core"]);
+    }
+
+    #[test]
+    fn libraries_keep_their_lockfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = CargoProject::new(dir.path());
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"demo\"").unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "").unwrap();
+        assert!(project.is_library());
+
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        assert!(!project.is_library());
+
+        fs::write(dir.path().join("Cargo.lock"), "version = 4").unwrap();
+        assert!(project.delete_lockfile().unwrap());
+        assert!(!dir.path().join("Cargo.lock").exists());
+        assert!(!project.delete_lockfile().unwrap());
+    }
+
+    #[test]
+    fn manifests_are_told_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let manifest = |dir: &str, manifest: &str| {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join("Cargo.toml"), manifest).unwrap();
+        };
+        manifest(
+            "",
+            "[workspace]\nmembers = [\"./crates/*\"]\nexclude = [\"crates/old\"]",
+        );
+        manifest("crates/core", "[package]\nname = \"core\"");
+        manifest("crates/old", "[package]\nname = \"old\"");
+        manifest(
+            "tools/gen",
+            "[package]\nname = \"gen\"\nworkspace = \"../..\"",
+        );
+        manifest("examples/demo", "[package]\nname = \"demo\"");
+
+        let kind = |dir: &str| CargoProject::new(root.join(dir)).kind();
+        assert_eq!(kind(""), ManifestKind::WorkspaceRoot);
+        assert_eq!(kind("crates/core"), ManifestKind::Member);
+        assert_eq!(kind("tools/gen"), ManifestKind::Member);
+        assert_eq!(kind("crates/old"), ManifestKind::Standalone);
+        assert_eq!(kind("examples/demo"), ManifestKind::Standalone);
+    }
+
+    #[test]
+    fn target_triple_is_removed_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        for profile in ["debug", "wasm32-unknown-unknown/debug"] {
+            fs::create_dir_all(target.join(profile)).unwrap();
+            fs::write(target.join(profile).join("lib.rlib"), [0u8; 2048]).unwrap();
+        }
+
+        let options = CleanOptions {
+            target: Some("wasm32-unknown-unknown".to_string()),
+            ..Default::default()
+        };
+        let out = CargoProject::new(dir.path())
+            .clean(&options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.files, 1);
+        assert_eq!(out.size_kib, 2.);
+        assert!(target.join("debug/lib.rlib").exists());
+        assert!(!target.join("wasm32-unknown-unknown").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn build_jobs_reach_cargo() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cargo = dir.path().join("cargo");
+        fs::write(
+            &cargo,
+            "#!/bin/sh\necho \"Removed $CARGO_BUILD_JOBS files, 1.0KiB total\" >&2\n",
+        )
+        .unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let out = CargoProject::new(dir.path())
+            .clean(&CleanOptions {
+                cargo,
+                build_jobs: Some(3),
+                ..Default::default()
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.files, 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn assume_freed_credits_the_measured_target() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("target/debug")).unwrap();
+        fs::write(dir.path().join("target/debug/app"), [0u8; 2048]).unwrap();
+        let cargo = dir.path().join("cargo");
+        fs::write(
+            &cargo,
+            "#!/bin/sh
+rm -r target
+echo '   Entfernt: 1 Datei' >&2
+",
+        )
+        .unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let out = CargoProject::new(dir.path())
+            .clean(&CleanOptions {
+                cargo,
+                assume_freed: true,
+                ..Default::default()
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!((out.files, out.size_kib), (1, 2.));
+        assert!(out.measured);
+        assert!(!dir.path().join("target").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cargo_runs_with_a_neutral_locale() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // stands in for a cargo that would translate its output
+        let dir = tempfile::tempdir().unwrap();
+        let cargo = dir.path().join("cargo");
+        fs::write(
+            &cargo,
+            "#!/bin/sh\nif [ \"$LC_ALL\" = C ] && [ \"$LANG\" = C ]; then\n  echo 'Removed 2 files, 1.0KiB total' >&2\nelse\n  echo '2 Dateien entfernt, insgesamt 1.0KiB' >&2\nfi\n",
+        )
+        .unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let out = CargoProject::new(dir.path())
+            .clean(&CleanOptions {
+                cargo,
+                ..Default::default()
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.files, 2);
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        assert!(is_transient_error(
+            "error: failed to remove file `target/debug/foo`\n\nCaused by:\n  Input/output error (os error 5)"
+        ));
+        assert!(is_transient_error("Stale file handle (os error 116)"));
+        assert!(!is_transient_error(
+            "error: could not find `Cargo.toml` in `/tmp/foo` or any parent directory"
+        ));
+        assert!(!is_transient_error(
+            "error: key with no value, expected `=`"
+        ));
+        let denied = "error: failed to remove file `target/debug/foo`\n\nCaused by:\n  Permission denied (os error 13)";
+        assert_eq!(is_transient_error(denied), cfg!(windows));
+    }
+
+    #[test]
+    fn file_is_not_a_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("main.rs");
+        fs::write(&file, "").unwrap();
+
+        assert!(!CargoProject::new(file).is_valid_project(false).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dangling_manifest_symlink_is_unreadable() {
+        let dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(dir.path().join("missing"), dir.path().join("Cargo.toml"))
+            .unwrap();
+        fs::create_dir(dir.path().join("target")).unwrap();
+
+        let project = CargoProject::new(dir.path());
+        assert!(project.is_valid_project(true).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn relative_cargo_paths_work_from_every_project() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let cwd = tempfile::tempdir().unwrap();
+        fs::create_dir(cwd.path().join("bin")).unwrap();
+        let cargo = cwd.path().join("bin/cargo");
+        fs::write(
+            &cargo,
+            "#!/bin/sh\necho 'Removed 2 files, 1.0KiB total' >&2\n",
+        )
+        .unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(
+            resolve_cargo("cargo".into(), cwd.path()),
+            Path::new("cargo")
+        );
+        assert_eq!(resolve_cargo(cargo.clone(), Path::new("/elsewhere")), cargo);
+
+        let project = tempfile::tempdir().unwrap();
+        let options = CleanOptions {
+            cargo: resolve_cargo("./bin/cargo".into(), cwd.path()),
+            ..Default::default()
+        };
+        let out = CargoProject::new(project.path()).clean(&options).unwrap();
+        assert_eq!(out.unwrap().files, 2);
+    }
+}
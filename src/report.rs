@@ -0,0 +1,413 @@
+use crate::stats::{CleanupStats, ProjectRecord, SizeFormat};
+use anyhow::{Context, bail};
+use console::style;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write, stdout};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// Version of the `--format json` document, bumped whenever a field is renamed, removed
+/// or changes its meaning. Added fields don't bump it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The document written by `--format json`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    /// `SCHEMA_VERSION` of the run that wrote it, 0 for reports from before it existed.
+    #[serde(default)]
+    pub schema_version: u32,
+    pub projects: Vec<ProjectRecord>,
+    /// The totals, included unless `--summary-format human` is asked for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+}
+
+impl From<&CleanupStats> for Report {
+    fn from(stats: &CleanupStats) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            projects: stats.records.clone(),
+            summary: None,
+        }
+    }
+}
+
+/// Where the report goes: stdout, or the file given to `--output-file` so it doesn't
+/// mix with the terminal output.
+pub enum Sink {
+    Stdout,
+    File(Mutex<File>),
+}
+
+impl Sink {
+    pub fn new(path: Option<&Path>) -> anyhow::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Sink::Stdout);
+        };
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Sink::File(Mutex::new(file)))
+    }
+
+    /// Write `line` and flush, so consumers see it right away. Files get it unstyled.
+    pub fn write_line(&self, line: &str) -> io::Result<()> {
+        match self {
+            Sink::Stdout => {
+                let mut out = stdout().lock();
+                writeln!(out, "{line}")?;
+                out.flush()
+            }
+            Sink::File(file) => {
+                writeln!(file.lock().unwrap(), "{}", console::strip_ansi_codes(line))
+            }
+        }
+    }
+}
+
+/// A line of `--format ndjson` output.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    ScanStart { dir: &'a Path },
+    ProjectCleaned(&'a ProjectRecord),
+    ProjectFailed { path: &'a Path, error: String },
+    Summary(&'a Summary),
+}
+
+/// Aggregate numbers of a run, as written by `--summary-file`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Summary {
+    pub projects: usize,
+    /// Crates covered by the cleaned targets, more than `projects` with workspaces.
+    #[serde(default)]
+    pub crates: usize,
+    pub files: usize,
+    pub size_kib: f64,
+    pub failed: usize,
+    /// Projects that couldn't be cleaned for lack of permissions, not counted in `failed`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permission_denied: Vec<PathBuf>,
+    pub elapsed_secs: f64,
+    /// Change of free space on the scanned filesystem, only with `--real-freed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_freed_kib: Option<f64>,
+}
+
+impl Summary {
+    pub fn new(stats: &CleanupStats, elapsed: Duration) -> Self {
+        Self {
+            projects: stats.projects,
+            crates: stats.crates,
+            files: stats.files,
+            size_kib: stats.size_kib,
+            failed: stats.failed,
+            permission_denied: stats.permission_denied.clone(),
+            elapsed_secs: elapsed.as_secs_f64(),
+            disk_freed_kib: stats.disk_freed_kib,
+        }
+    }
+
+    /// A CSV header and a row with the totals.
+    pub fn to_csv(&self) -> String {
+        format!(
+            "projects,files,size_kib,failed,elapsed_secs\n{},{},{},{},{}",
+            self.projects, self.files, self.size_kib, self.failed, self.elapsed_secs
+        )
+    }
+
+    /// Write the summary as JSON, replacing `path` atomically so readers never
+    /// see a partially written file.
+    pub fn write_atomic(&self, path: &Path) -> anyhow::Result<()> {
+        write_json_atomic(path, self)
+    }
+}
+
+/// Placeholders understood by `--summary-template`.
+const PLACEHOLDERS: [&str; 5] = ["projects", "files", "size", "bytes", "elapsed"];
+
+static PLACEHOLDER_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{([^{}]*)\}").expect("Invalid regex"));
+
+/// A custom summary line such as `freed {size} across {projects} projects in {elapsed}`.
+#[derive(Debug, Clone)]
+pub struct SummaryTemplate(String);
+
+impl SummaryTemplate {
+    /// Check that the template only uses known placeholders.
+    pub fn parse(template: &str) -> Result<Self, String> {
+        for caps in PLACEHOLDER_RE.captures_iter(template) {
+            if !PLACEHOLDERS.contains(&&caps[1]) {
+                return Err(format!(
+                    "unknown placeholder `{}`, available: {}",
+                    &caps[0],
+                    PLACEHOLDERS.map(|name| format!("{{{name}}}")).join(", ")
+                ));
+            }
+        }
+        Ok(Self(template.to_owned()))
+    }
+
+    pub fn render(&self, summary: &Summary, size_format: SizeFormat) -> String {
+        PLACEHOLDER_RE
+            .replace_all(&self.0, |caps: &regex::Captures| match &caps[1] {
+                "projects" => summary.projects.to_string(),
+                "files" => summary.files.to_string(),
+                "size" => size_format.format(summary.size_kib),
+                "bytes" => ((summary.size_kib * 1024.).round() as u64).to_string(),
+                "elapsed" => format!("{:.2}s", summary.elapsed_secs),
+                _ => unreachable!("placeholders are checked when parsing"),
+            })
+            .into_owned()
+    }
+}
+
+/// Totals over every run recorded in a `--stats-file`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Lifetime {
+    pub runs: usize,
+    pub projects: usize,
+    pub files: usize,
+    pub size_kib: f64,
+}
+
+impl Lifetime {
+    /// The totals so far, empty when the file doesn't exist yet.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let json = match fs::read(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        serde_json::from_slice(&json)
+            .with_context(|| format!("Invalid stats file {}", path.display()))
+    }
+
+    /// Count a run with the given totals.
+    pub fn add(&mut self, summary: &Summary) {
+        self.runs += 1;
+        self.projects += summary.projects;
+        self.files += summary.files;
+        self.size_kib += summary.size_kib;
+    }
+
+    pub fn write_atomic(&self, path: &Path) -> anyhow::Result<()> {
+        write_json_atomic(path, self)
+    }
+}
+
+/// Write `value` next to `path` first, so that readers never see a partial file.
+fn write_json_atomic(path: &Path, value: &impl Serialize) -> anyhow::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, serde_json::to_vec(value)?)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+impl Event<'_> {
+    /// Write the event to `sink` as a single line.
+    pub fn emit(&self, sink: &Sink) -> anyhow::Result<()> {
+        sink.write_line(&serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// How a project changed between a baseline report and the current run.
+pub enum Change {
+    New(f64),
+    Gone(f64),
+    Grown { from: f64, to: f64 },
+    Shrunk { from: f64, to: f64 },
+}
+
+pub struct DiffLine {
+    pub path: PathBuf,
+    pub change: Change,
+    pub size_format: SizeFormat,
+}
+
+impl Report {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read baseline {}", path.display()))?;
+        let report: Self = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse baseline {}", path.display()))?;
+        if report.schema_version > SCHEMA_VERSION {
+            bail!(
+                "Baseline {} has schema version {}, this version only reads up to {SCHEMA_VERSION}",
+                path.display(),
+                report.schema_version
+            );
+        }
+        Ok(report)
+    }
+
+    /// The document as a single line, or indented over several for reading.
+    pub fn to_json(&self, pretty: bool) -> serde_json::Result<String> {
+        if pretty {
+            serde_json::to_string_pretty(self)
+        } else {
+            serde_json::to_string(self)
+        }
+    }
+
+    /// Compare against `baseline`, keyed by project path.
+    ///
+    /// Projects with an unchanged size are left out, the others print their sizes as
+    /// `size_format` says.
+    pub fn diff(&self, baseline: &Report, size_format: SizeFormat) -> Vec<DiffLine> {
+        let before = sizes(baseline);
+        let after = sizes(self);
+
+        let mut lines = Vec::new();
+        for (path, &to) in &after {
+            let change = match before.get(path) {
+                None => Change::New(to),
+                Some(&from) if to > from => Change::Grown { from, to },
+                Some(&from) if to < from => Change::Shrunk { from, to },
+                Some(_) => continue,
+            };
+            lines.push(DiffLine {
+                path: path.to_path_buf(),
+                change,
+                size_format,
+            });
+        }
+        for (path, &from) in &before {
+            if !after.contains_key(path) {
+                lines.push(DiffLine {
+                    path: path.to_path_buf(),
+                    change: Change::Gone(from),
+                    size_format,
+                });
+            }
+        }
+        lines
+    }
+}
+
+fn sizes(report: &Report) -> BTreeMap<&Path, f64> {
+    report
+        .projects
+        .iter()
+        .map(|p| (p.path.as_path(), p.size_kib))
+        .collect()
+}
+
+impl fmt::Display for DiffLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = |size_kib| self.size_format.format(size_kib);
+        let path = self.path.display();
+        match self.change {
+            Change::New(to) => write!(
+                f,
+                "{:>12} {} in {path}",
+                style("New").bold().yellow(),
+                size(to)
+            ),
+            Change::Gone(from) => write!(
+                f,
+                "{:>12} {} in {path}",
+                style("Gone").bold().green(),
+                size(from)
+            ),
+            Change::Grown { from, to } => write!(
+                f,
+                "{:>12} {} -> {} in {path}",
+                style("Grown").bold().yellow(),
+                size(from),
+                size(to)
+            ),
+            Change::Shrunk { from, to } => write!(
+                f,
+                "{:>12} {} -> {} in {path}",
+                style("Shrunk").bold().green(),
+                size(from),
+                size(to)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_carry_their_schema_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        let report = Report::from(&CleanupStats::default());
+        let pretty = report.to_json(true).unwrap();
+        assert!(pretty.contains("\n  \"schema_version\": 1,"), "{pretty}");
+        fs::write(&path, &pretty).unwrap();
+        assert_eq!(Report::load(&path).unwrap().schema_version, SCHEMA_VERSION);
+
+        // baselines from before the field existed still load
+        fs::write(&path, r#"{"projects":[]}"#).unwrap();
+        assert_eq!(Report::load(&path).unwrap().schema_version, 0);
+
+        fs::write(&path, r#"{"schema_version":99,"projects":[]}"#).unwrap();
+        assert!(Report::load(&path).is_err());
+    }
+
+    #[test]
+    fn lifetime_totals_accumulate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+        let summary = Summary {
+            projects: 2,
+            crates: 2,
+            files: 10,
+            size_kib: 1.5,
+            failed: 0,
+            permission_denied: Vec::new(),
+            elapsed_secs: 1.,
+            disk_freed_kib: None,
+        };
+        for _ in 0..2 {
+            let mut lifetime = Lifetime::load(&path).unwrap();
+            lifetime.add(&summary);
+            lifetime.write_atomic(&path).unwrap();
+        }
+        let lifetime = Lifetime::load(&path).unwrap();
+        assert_eq!(
+            (lifetime.runs, lifetime.projects, lifetime.files),
+            (2, 4, 20)
+        );
+        assert_eq!(lifetime.size_kib, 3.);
+
+        fs::write(&path, "{not json").unwrap();
+        assert!(Lifetime::load(&path).is_err());
+    }
+
+    #[test]
+    fn summary_template_fills_in_placeholders() {
+        let summary = Summary {
+            projects: 3,
+            crates: 3,
+            files: 42,
+            size_kib: 2048.,
+            failed: 0,
+            permission_denied: Vec::new(),
+            elapsed_secs: 1.5,
+            disk_freed_kib: None,
+        };
+        let template = SummaryTemplate::parse(
+            "freed {size} ({bytes} bytes) across {projects} projects in {elapsed}",
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&summary, SizeFormat::Human),
+            "freed 2.00MiB (2097152 bytes) across 3 projects in 1.50s"
+        );
+        let e = SummaryTemplate::parse("{projects} in {duration}").unwrap_err();
+        assert!(e.contains("`{duration}`"), "{e}");
+    }
+}
@@ -0,0 +1,1896 @@
+use crate::cli::{AgeField, OutputFormat, SortOrder};
+use crate::error::CleanError;
+use crate::filter::PathFilter;
+use crate::project::{CargoProject, CleanOptions, CleanOutput, ManifestKind, TargetEnv, summarize};
+use crate::report::{Event, Sink};
+use crate::size::{dir_size, format_profiles, profile_sizes};
+use crate::size_cache::SizeCache;
+use crate::stats::{CleanupStats, SizeFormat};
+use crate::ui::{JobLine, Ui, report_progress};
+use anyhow::bail;
+use console::style;
+use regex::Regex;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use walkdir::WalkDir;
+
+/// Everything `process_directory` needs to decide what to clean.
+pub struct ScanOptions {
+    /// Only projects last modified before this instant are cleaned.
+    pub before: Option<SystemTime>,
+    /// Only projects last modified after this instant are cleaned.
+    pub after: Option<SystemTime>,
+    /// Replacements for `before` below these canonical directories, the longest match wins.
+    pub rules: Vec<(PathBuf, SystemTime)>,
+    /// Only projects last accessed before this instant are cleaned.
+    pub accessed_before: Option<SystemTime>,
+    /// Hold the last access time to the project's `before` bound as well, rules included.
+    pub older_than_accessed: bool,
+    /// Only projects last accessed after this instant are cleaned.
+    pub accessed_after: Option<SystemTime>,
+    /// Skip instead of clean projects whose modification time can't be read
+    /// because the platform doesn't support it.
+    pub skip_without_mtime: bool,
+    /// Judge git repositories by their last commit instead of the modification time.
+    pub git_commit_time: bool,
+    /// Which timestamp of the project directory `before` and `after` compare against.
+    pub age_field: AgeField,
+    /// Take the newest timestamp of any file in the project outside its target instead
+    /// of the directory's own, which many filesystems leave alone when files are edited.
+    pub deep_mtime: bool,
+    pub filter: PathFilter,
+    /// Don't look for further projects inside a project's directory.
+    pub no_nested: bool,
+    /// Skip crates that are members of a workspace further up.
+    pub exclude_workspace_members: bool,
+    /// Read directories on several threads while scanning.
+    pub parallel_walk: bool,
+    /// Version line of the cargo binary, mentioned when its output isn't understood.
+    pub cargo_version: String,
+    /// Names of the target directory below a project, `target` when empty.
+    pub target_names: Vec<String>,
+    /// `CARGO_TARGET_DIR` and the cargo home the target directories are resolved with.
+    pub target_env: TargetEnv,
+    /// Only consider the scanned directory itself instead of walking it.
+    pub single_project: bool,
+    /// Don't descend into git submodules listed in any `.gitmodules` met on the way.
+    pub skip_submodules: bool,
+    /// Skip projects whose `[package] name` is one of these.
+    pub exclude_names: Vec<String>,
+    /// Only clean projects whose package name, or one of its workspace members', matches.
+    pub name: Option<Regex>,
+    /// Only remove incremental compilation caches instead of running `cargo clean`.
+    pub incremental_only: bool,
+    /// Only measure target directories instead of cleaning them, implies a dry run.
+    pub stats_only: bool,
+    /// Only print the paths of the projects that pass the filters, in cleaning order.
+    pub scan_only: bool,
+    /// Sizes remembered from earlier `stats_only` runs.
+    pub size_cache: Option<SizeCache>,
+    pub clean: CleanOptions,
+    /// Remove target directories left without any files after cleaning.
+    pub prune_empty_target: bool,
+    /// Remove `Cargo.lock` from every cleaned project that isn't only a library.
+    pub delete_lockfile: bool,
+    /// Abort on the first failed clean instead of carrying on.
+    pub fail_fast: bool,
+    /// Set by the Ctrl-C handler, no further projects are started once it is. Those being
+    /// cleaned are finished so no target is left half removed.
+    pub interrupted: Arc<AtomicBool>,
+    /// How many projects are cleaned at the same time.
+    pub jobs: usize,
+    /// How many of those may remove files at the same time, `jobs` when unset.
+    pub io_parallelism: Option<usize>,
+    /// Where `done/total` lines are written for wrapping tools, see `--progress-fd`.
+    pub progress_fd: Option<File>,
+    /// Where the ndjson events go.
+    pub sink: Sink,
+    pub format: OutputFormat,
+    /// How sizes are printed in the per-project lines and the summary.
+    pub size_format: SizeFormat,
+    /// Print a line for each project that had nothing to remove or was opted out with
+    /// `.cargocleanignore`, instead of only counting it.
+    pub list_already_clean: bool,
+    /// Treat every directory with a `Cargo.toml` as a project, even without a target.
+    pub no_target_required: bool,
+    /// Leave the projects with the newest modification times alone.
+    pub keep_latest: Option<usize>,
+    /// Measure targets again after cleaning and warn when more than this many bytes are left.
+    pub verify: Option<u64>,
+    /// Skip projects whose target directory is smaller than this many bytes.
+    pub min_size: Option<u64>,
+    /// Note projects whose target is larger than this many bytes, see `CleanupStats::oversized`.
+    pub fail_on_size: Option<u64>,
+    /// Clean the largest projects first and stop once this many bytes were freed.
+    pub free_at_least: Option<u64>,
+    /// Abort before cleaning anything when fewer projects than this pass the filters.
+    pub min_projects: Option<usize>,
+    /// Replaces the word labelling per-project lines and the cleaning spinner.
+    pub label: Option<String>,
+    /// Order to clean the projects in, the walk order when unset.
+    pub sort: Option<SortOrder>,
+    /// With several jobs, hold the per-project lines back and print them in queue order
+    /// once everything is cleaned, the order a single job would print them in.
+    pub stable_order: bool,
+    /// Measure profile directories before cleaning to break the freed space down.
+    pub by_profile: bool,
+    /// Time every clean and keep the durations in the records.
+    pub timings: bool,
+    /// Command run in every cleaned project, `{path}` is replaced by its path.
+    pub exec: Option<String>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            before: None,
+            after: None,
+            rules: Vec::new(),
+            accessed_before: None,
+            older_than_accessed: false,
+            accessed_after: None,
+            skip_without_mtime: false,
+            git_commit_time: false,
+            age_field: AgeField::Mtime,
+            deep_mtime: false,
+            filter: PathFilter::default(),
+            no_nested: false,
+            exclude_workspace_members: false,
+            parallel_walk: false,
+            skip_submodules: false,
+            target_env: TargetEnv::default(),
+            single_project: false,
+            target_names: Vec::new(),
+            cargo_version: String::new(),
+            exclude_names: Vec::new(),
+            name: None,
+            incremental_only: false,
+            stats_only: false,
+            scan_only: false,
+            stable_order: false,
+            label: None,
+            size_cache: None,
+            clean: CleanOptions::default(),
+            prune_empty_target: false,
+            delete_lockfile: false,
+            fail_fast: false,
+            interrupted: Arc::default(),
+            jobs: 1,
+            io_parallelism: None,
+            progress_fd: None,
+            sink: Sink::Stdout,
+            format: OutputFormat::Human,
+            size_format: SizeFormat::Human,
+            no_target_required: false,
+            list_already_clean: false,
+            keep_latest: None,
+            min_size: None,
+            verify: None,
+            fail_on_size: None,
+            free_at_least: None,
+            sort: None,
+            min_projects: None,
+            by_profile: false,
+            timings: false,
+            exec: None,
+        }
+    }
+}
+
+fn removed_verb(dry_run: bool) -> &'static str {
+    if dry_run { "Would remove" } else { "Removed" }
+}
+
+impl ScanOptions {
+    /// Label of the per-project lines, `--label` or what fits the run.
+    pub fn removed_label(&self) -> &str {
+        self.label
+            .as_deref()
+            .unwrap_or(removed_verb(self.clean.dry_run))
+    }
+
+    /// The instant a project at `path` must have been last modified before, from the most
+    /// specific rule covering it or `before`.
+    fn before_for(&self, path: &Path) -> Option<SystemTime> {
+        if self.rules.is_empty() {
+            return self.before;
+        }
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.rules
+            .iter()
+            .filter(|(dir, _)| path.starts_with(dir))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .map_or(self.before, |&(_, before)| Some(before))
+    }
+
+    /// Spinner prefix while a project is being cleaned.
+    fn cleaning_label(&self) -> Cow<'static, str> {
+        self.label
+            .clone()
+            .map_or(Cow::Borrowed("Cleaning"), Cow::Owned)
+    }
+}
+
+/// Directory walk yielding every entry below the scanned directory with whether it is
+/// a directory.
+enum Walker {
+    /// Leaves out submodules with `--skip-submodules`.
+    Sequential(walkdir::FilterEntry<walkdir::IntoIter, SubmoduleFilter>),
+    /// Reads directories on several threads, already leaving out submodules, ignored
+    /// projects' contents and those of any project with `--no-nested`.
+    Parallel(jwalk::DirEntryIter<((), ())>),
+    /// Just the scanned directory, with `--manifest-path`.
+    Single(Option<PathBuf>),
+}
+
+type SubmoduleFilter = Box<dyn FnMut(&walkdir::DirEntry) -> bool>;
+
+impl Walker {
+    fn new(dir: &Path, options: &ScanOptions) -> Self {
+        if options.single_project {
+            return Walker::Single(Some(dir.to_path_buf()));
+        }
+        let skip_submodules = options.skip_submodules;
+        if !options.parallel_walk {
+            // directories are met before their contents, so every `.gitmodules` is read
+            // before the submodules it lists come up
+            let mut submodules = HashSet::new();
+            let filter: SubmoduleFilter = Box::new(move |entry| {
+                if !skip_submodules || !entry.file_type().is_dir() {
+                    return true;
+                }
+                if submodules.contains(entry.path()) {
+                    return false;
+                }
+                submodules.extend(submodule_paths(entry.path()));
+                true
+            });
+            return Walker::Sequential(WalkDir::new(dir).into_iter().filter_entry(filter));
+        }
+        let no_nested = options.no_nested;
+        let require_target = !options.no_target_required;
+        let target_names = options.target_names.clone();
+        let target_env = options.target_env.clone();
+        // a directory is only read after its parent, so its `.gitmodules` is known by then
+        let submodules = Mutex::new(HashSet::new());
+        let walk = jwalk::WalkDir::new(dir)
+            .sort(true)
+            .skip_hidden(false)
+            .process_read_dir(move |_, dir, _, children| {
+                let project = CargoProject::new(dir)
+                    .with_target_names(&target_names)
+                    .with_target_env(&target_env);
+                if (no_nested || project.is_ignored())
+                    && project.is_valid_project(require_target).unwrap_or(false)
+                {
+                    children.clear();
+                }
+                if skip_submodules {
+                    let mut submodules = submodules.lock().unwrap();
+                    submodules.extend(submodule_paths(dir));
+                    children.retain(|child| {
+                        child
+                            .as_ref()
+                            .is_ok_and(|child| !submodules.contains(&child.path()))
+                    });
+                }
+            });
+        Walker::Parallel(walk.into_iter())
+    }
+
+    fn next(&mut self) -> Option<(PathBuf, bool)> {
+        match self {
+            Walker::Sequential(walk) => walk
+                .find_map(|e| e.ok())
+                .map(|e| (e.path().to_path_buf(), e.file_type().is_dir())),
+            Walker::Parallel(walk) => walk
+                .find_map(|e| e.ok())
+                .map(|e| (e.path(), e.file_type().is_dir())),
+            Walker::Single(dir) => dir.take().map(|dir| (dir, true)),
+        }
+    }
+
+    /// Don't descend into the directory just returned by `next`.
+    fn skip_current_dir(&mut self) {
+        match self {
+            Walker::Sequential(walk) => walk.skip_current_dir(),
+            // pruned while reading the directory
+            Walker::Parallel(_) => {}
+            Walker::Single(_) => {}
+        }
+    }
+}
+
+/// The submodule directories listed in `repo/.gitmodules`, if there is one.
+fn submodule_paths(repo: &Path) -> Vec<PathBuf> {
+    let Ok(gitmodules) = fs::read_to_string(repo.join(".gitmodules")) else {
+        return Vec::new();
+    };
+    gitmodules
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == "path").then(|| repo.join(value.trim()))
+        })
+        .collect()
+}
+
+/// The timestamp of `path` the age filters compare against.
+fn age_time(path: &Path, field: AgeField) -> io::Result<SystemTime> {
+    let metadata = path.metadata()?;
+    match field {
+        AgeField::Mtime => metadata.modified(),
+        AgeField::Atime => metadata.accessed(),
+        #[cfg(unix)]
+        AgeField::Ctime => {
+            use std::os::unix::fs::MetadataExt;
+            let secs = u64::try_from(metadata.ctime()).unwrap_or_default();
+            let nanos = u32::try_from(metadata.ctime_nsec()).unwrap_or_default();
+            Ok(SystemTime::UNIX_EPOCH + Duration::new(secs, nanos))
+        }
+        #[cfg(not(unix))]
+        AgeField::Ctime => Err(io::ErrorKind::Unsupported.into()),
+    }
+}
+
+/// When `project` was last used going by `field`: the time of its directory, or with
+/// `deep` the newest of everything in it apart from target directories, which building
+/// touches without the project being worked on.
+fn project_time(project: &CargoProject, field: AgeField, deep: bool) -> io::Result<SystemTime> {
+    let newest = age_time(project.path(), field)?;
+    if !deep {
+        return Ok(newest);
+    }
+    // nested projects' targets are left out as well
+    let target = project.target_dir();
+    let newest = WalkDir::new(project.path())
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| e.path() != target && e.file_name() != "target")
+        .filter_map(|e| age_time(e.ok()?.path(), field).ok())
+        .fold(newest, SystemTime::max);
+    Ok(newest)
+}
+
+/// Whether the filesystem holding `dir` is mounted without updating access times.
+#[cfg(target_os = "linux")]
+fn noatime_mount(dir: &Path) -> bool {
+    let (Ok(dir), Ok(mounts)) = (dir.canonicalize(), fs::read_to_string("/proc/self/mounts"))
+    else {
+        return false;
+    };
+    // the innermost mount point wins, of several on the same point the last one
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let point = fields.nth(1)?.replace("\\040", " ");
+            let options = fields.nth(1)?;
+            Some((point, options))
+        })
+        .filter(|(point, _)| dir.starts_with(point))
+        .max_by_key(|(point, _)| point.len())
+        .is_some_and(|(_, options)| options.split(',').any(|option| option == "noatime"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn noatime_mount(_dir: &Path) -> bool {
+    false
+}
+
+/// Walk `dir` and run `cargo clean` in every project that passes the filters.
+pub fn process_directory(
+    dir: &Path,
+    options: &ScanOptions,
+    ui: &Ui,
+) -> anyhow::Result<CleanupStats> {
+    let mut stats = CleanupStats {
+        size_format: options.size_format,
+        ..Default::default()
+    };
+    // canonical target directories already handled, symlinks may lead back into the tree
+    // or several projects may share one target
+    let mut seen = HashSet::new();
+    // projects that passed the path filters, with their modification time if it was read
+    let mut found = Vec::new();
+    // and those of them that also passed the age and size filters
+    let mut candidates = Vec::new();
+    let ndjson = options.format == OutputFormat::Ndjson;
+    let mut mtime_unsupported = false;
+    let mut atime_unsupported = false;
+    if ndjson {
+        Event::ScanStart { dir }.emit(&options.sink)?;
+    }
+
+    // a mount without access times would make every project look idle since it was mounted
+    let mut age_field = options.age_field;
+    if age_field == AgeField::Atime && noatime_mount(dir) {
+        ui.warn(format!(
+            "{:>12} {} is mounted noatime, using modification times instead",
+            style("Warning").bold().yellow(),
+            dir.display()
+        ));
+        age_field = AgeField::Mtime;
+    }
+
+    let mut walker = Walker::new(dir, options);
+    while let Some((path, is_dir)) = walker.next() {
+        if options.interrupted.load(Ordering::Relaxed) {
+            break;
+        }
+        ui.status("Scanning", path.display());
+
+        let project = CargoProject::new(&path)
+            .with_target_names(&options.target_names)
+            .with_target_env(&options.target_env);
+        match project.is_valid_project(!options.no_target_required) {
+            // members of an ignored workspace share its target, so they are left out too
+            Ok(true) if project.is_ignored() => {
+                walker.skip_current_dir();
+                stats.skipped.ignored += 1;
+                if options.list_already_clean {
+                    ui.println(format!(
+                        "{:>12} {}",
+                        style("Ignored").bold().dim(),
+                        project.path().display()
+                    ));
+                }
+                continue;
+            }
+            Ok(true) => {
+                if options.no_nested {
+                    walker.skip_current_dir();
+                }
+            }
+            Ok(false) => {
+                if project.path().join("Cargo.toml").exists() {
+                    stats.skipped.no_target += 1;
+                } else if is_dir {
+                    stats.skipped.no_manifest += 1;
+                }
+                continue;
+            }
+            Err(e) => {
+                ui.warn(format!(
+                    "{:>12} skipping {}: unreadable Cargo.toml: {e}",
+                    style("Warning").bold().yellow(),
+                    project.path().display()
+                ));
+                continue;
+            }
+        }
+        if !options.filter.is_match(dir, project.path()) {
+            stats.skipped.excluded += 1;
+            continue;
+        }
+        if let Some(name) = project.package_name()
+            && options
+                .exclude_names
+                .iter()
+                .any(|excluded| excluded == name)
+        {
+            stats.skipped.excluded += 1;
+            continue;
+        }
+        if let Some(pattern) = &options.name
+            && !project
+                .package_names()
+                .iter()
+                .any(|name| pattern.is_match(name))
+        {
+            stats.skipped.excluded += 1;
+            continue;
+        }
+        if options.exclude_workspace_members && project.kind() == ManifestKind::Member {
+            stats.skipped.excluded += 1;
+            continue;
+        }
+        // `cargo clean -p` only makes sense against a workspace's shared target
+        if !options.clean.packages.is_empty() && !project.is_workspace_root() {
+            stats.skipped.not_workspace_root += 1;
+            continue;
+        }
+        // read the modification time if anything goes by it, the window is checked below
+        let before = options.before_for(project.path());
+        let needs_mtime = before.is_some()
+            || options.after.is_some()
+            || options.keep_latest.is_some()
+            || options.sort == Some(SortOrder::Mtime);
+        let modified = if needs_mtime {
+            let committed = options
+                .git_commit_time
+                .then(|| project.last_commit_time())
+                .flatten();
+            let modified = match committed {
+                Some(committed) => Ok(committed),
+                None => match project_time(&project, age_field, options.deep_mtime) {
+                    Err(e)
+                        if e.kind() == io::ErrorKind::Unsupported
+                            && age_field != AgeField::Mtime =>
+                    {
+                        ui.warn(format!(
+                            "{:>12} {} times are not available here, using modification times instead",
+                            style("Warning").bold().yellow(),
+                            if age_field == AgeField::Atime { "access" } else { "change" }
+                        ));
+                        age_field = AgeField::Mtime;
+                        project_time(&project, age_field, options.deep_mtime)
+                    }
+                    time => time,
+                },
+            };
+            match modified {
+                Ok(modified) => Some(modified),
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                    if !mtime_unsupported {
+                        mtime_unsupported = true;
+                        ui.warn(format!(
+                            "{:>12} modification times are not available here, {} projects regardless of --past and --newer-than",
+                            style("Warning").bold().yellow(),
+                            if options.skip_without_mtime { "skipping" } else { "cleaning" }
+                        ));
+                    }
+                    if options.skip_without_mtime {
+                        continue;
+                    }
+                    None
+                }
+                Err(e) => {
+                    ui.warn(format!(
+                        "{:>12} skipping {}: failed to read the modification time: {e}",
+                        style("Warning").bold().yellow(),
+                        project.path().display()
+                    ));
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+        // read during the walk, before listing the directory moves its access time along
+        let accessed = (options.accessed_before.is_some()
+            || options.accessed_after.is_some()
+            || (options.older_than_accessed && before.is_some()))
+        .then(|| path.metadata().and_then(|m| m.accessed()));
+        found.push((project, before, modified, accessed));
+    }
+
+    if let Some(keep) = options.keep_latest {
+        // ranked before the age and size filters, so the newest projects overall are kept
+        // rather than the newest of those old enough to clean; projects without a known
+        // modification time sort last and are never kept
+        let mut newest: Vec<_> = (0..found.len()).collect();
+        newest.sort_by_key(|&i| Reverse(found[i].2));
+        newest.truncate(keep);
+        newest.retain(|&i| found[i].2.is_some());
+        newest.sort_unstable();
+        for &i in newest.iter().rev() {
+            let (project, ..) = found.remove(i);
+            // other projects building into a kept target would clean it after all
+            seen.insert(project.target_key());
+            ui.println(format!(
+                "{:>12} {}, recently modified",
+                style("Kept").bold().green(),
+                project.path().display()
+            ));
+        }
+        stats.kept_latest = newest.len();
+    }
+
+    for (project, before, modified, accessed) in found {
+        if let Some(modified) = modified {
+            if before.is_some_and(|before| modified > before) {
+                stats.skipped.too_recent += 1;
+                continue;
+            }
+            if options.after.is_some_and(|after| modified < after) {
+                stats.skipped.too_old += 1;
+                continue;
+            }
+        }
+        // the earlier bound wins when --accessed-before is given as well
+        let accessed_before = [
+            options.accessed_before,
+            before.filter(|_| options.older_than_accessed),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        if let Some(accessed) = accessed
+            && !atime_unsupported
+        {
+            match accessed {
+                Ok(accessed) if accessed_before.is_some_and(|before| accessed > before) => {
+                    stats.skipped.too_recent += 1;
+                    continue;
+                }
+                Ok(accessed) if options.accessed_after.is_some_and(|after| accessed < after) => {
+                    stats.skipped.too_old += 1;
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                    atime_unsupported = true;
+                    ui.warn(format!(
+                        "{:>12} access times are not available here, only checking modification times",
+                        style("Warning").bold().yellow(),
+                    ));
+                }
+                Err(e) => {
+                    ui.warn(format!(
+                        "{:>12} skipping {}: failed to read the access time: {e}",
+                        style("Warning").bold().yellow(),
+                        project.path().display()
+                    ));
+                    continue;
+                }
+            }
+        }
+
+        // a shared target is measured once, for the first project building into it
+        if !seen.insert(project.target_key()) {
+            stats.skipped.shared_target += 1;
+            continue;
+        }
+        let target_bytes = (options.min_size.is_some() || options.fail_on_size.is_some())
+            .then(|| dir_size(&project.target_dir()).bytes);
+        if let Some(min_size) = options.min_size
+            && target_bytes < Some(min_size)
+        {
+            stats.skipped.too_small += 1;
+            continue;
+        }
+        if let Some(limit) = options.fail_on_size
+            && let Some(bytes) = target_bytes
+            && bytes > limit
+        {
+            stats.oversized.push((project.path().to_path_buf(), bytes));
+        }
+        candidates.push((project, modified));
+    }
+
+    if let Some(min_projects) = options.min_projects
+        && candidates.len() < min_projects
+    {
+        bail!(
+            "Found {} projects to clean, expected at least {min_projects}; check the directory and the filters",
+            candidates.len()
+        );
+    }
+
+    // a budget is met soonest by the largest projects
+    let sort = if options.free_at_least.is_some() {
+        stats.budget = options.free_at_least;
+        Some(SortOrder::Size)
+    } else {
+        options.sort
+    };
+    match sort {
+        Some(SortOrder::Path) => candidates.sort_by(|(a, _), (b, _)| a.path().cmp(b.path())),
+        Some(SortOrder::Size) => candidates
+            .sort_by_cached_key(|(project, _)| Reverse(dir_size(&project.target_dir()).bytes)),
+        // projects without a known modification time sort last
+        Some(SortOrder::Mtime) => {
+            candidates.sort_by_key(|&(_, modified)| (modified.is_none(), modified))
+        }
+        None => {}
+    }
+
+    if options.scan_only {
+        for (project, _) in &candidates {
+            options
+                .sink
+                .write_line(&project.path().display().to_string())?;
+        }
+        return Ok(stats);
+    }
+
+    let total = candidates.len();
+    if let Some(file) = &options.progress_fd {
+        report_progress(file, 0, total);
+    }
+    let jobs = options.jobs.clamp(1, total.max(1));
+    let queue = Mutex::new(
+        candidates
+            .into_iter()
+            .map(|(project, _)| project)
+            .enumerate(),
+    );
+    // a single job finishes the projects in queue order anyway
+    let held: Option<HeldLines> =
+        (options.stable_order && jobs > 1).then(|| Mutex::new(Vec::new()));
+    let done = AtomicUsize::new(0);
+    let aborted = AtomicBool::new(false);
+    let stats = Mutex::new(stats);
+    let io = Semaphore::new(options.io_parallelism.unwrap_or(jobs).max(1));
+    let cleaned = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| -> anyhow::Result<()> {
+                    // with a single job the overall spinner shows the project itself
+                    let line = if jobs > 1 {
+                        ui.job_line()
+                    } else {
+                        JobLine::default()
+                    };
+                    while !aborted.load(Ordering::Relaxed)
+                        && !options.interrupted.load(Ordering::Relaxed)
+                    {
+                        if let Some(budget) = options.free_at_least
+                            && stats.lock().unwrap().size_kib * 1024. >= budget as f64
+                        {
+                            break;
+                        }
+                        let Some((position, project)) = queue.lock().unwrap().next() else {
+                            break;
+                        };
+                        if jobs > 1 {
+                            line.status(options.cleaning_label(), project.path().display());
+                        } else {
+                            ui.status(options.cleaning_label(), project.path().display());
+                        }
+                        let held = held.as_ref().map(|held| (held, position));
+                        let cleaned = clean_project(&project, options, ui, &stats, &io, held);
+                        let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                        if let Some(file) = &options.progress_fd {
+                            report_progress(file, done, total);
+                        }
+                        if jobs > 1 {
+                            ui.status(options.cleaning_label(), format!("{done}/{total} projects"));
+                        }
+                        if cleaned.is_err() {
+                            aborted.store(true, Ordering::Relaxed);
+                        }
+                        cleaned?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().expect("cleaning thread panicked"))
+    });
+
+    // what was cleaned before a `fail_fast` abort is still worth showing
+    if let Some(held) = held {
+        let mut lines = held.into_inner().unwrap();
+        lines.sort_by_key(|&(position, _)| position);
+        for (_, line) in lines {
+            ui.println(line);
+        }
+    }
+    cleaned?;
+
+    let mut stats = stats.into_inner().unwrap();
+    let left = queue.into_inner().unwrap().count();
+    if options.interrupted.load(Ordering::Relaxed) {
+        stats.interrupted = true;
+        stats.skipped.interrupted = left;
+    } else {
+        stats.skipped.over_budget = left;
+    }
+    Ok(stats)
+}
+
+/// Per-project lines kept for `ScanOptions::stable_order`, with the project's queue position.
+type HeldLines = Mutex<Vec<(usize, String)>>;
+
+/// Bounds how many threads remove files at once, see `ScanOptions::io_parallelism`.
+struct Semaphore {
+    free: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Held while removing files, returned to the semaphore when dropped.
+struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Self {
+            free: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) -> Permit<'_> {
+        let mut free = self.free.lock().unwrap();
+        while *free == 0 {
+            free = self.released.wait(free).unwrap();
+        }
+        *free -= 1;
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.free.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// Clean a single project that passed the filters and record the outcome in `stats`.
+///
+/// Only returns an error to abort the whole run, i.e. with `--fail-fast` or when the
+/// ndjson output can't be written.
+fn clean_project(
+    project: &CargoProject,
+    options: &ScanOptions,
+    ui: &Ui,
+    stats: &Mutex<CleanupStats>,
+    io: &Semaphore,
+    held: Option<(&HeldLines, usize)>,
+) -> anyhow::Result<()> {
+    // warnings still show up right away, only the regular lines wait for their turn
+    let println = |line: String| match held {
+        Some((held, position)) => held.lock().unwrap().push((position, line)),
+        None => ui.println(line),
+    };
+    let ndjson = options.format == OutputFormat::Ndjson;
+    let profiles = options
+        .by_profile
+        .then(|| profile_sizes(&project.target_dir()));
+
+    // a dry run or a mere measurement leaves nothing to verify
+    let size_before = options
+        .verify
+        .filter(|_| !options.clean.dry_run && !options.stats_only)
+        .map(|_| dir_size(&project.target_dir()).bytes);
+
+    let permit = io.acquire();
+    let started = Instant::now();
+    let out = if options.stats_only {
+        let target = project.target_dir();
+        let size = match &options.size_cache {
+            Some(cache) => cache.dir_size(&target),
+            None => dir_size(&target),
+        };
+        Ok((size.files > 0).then(|| CleanOutput {
+            files: size.files,
+            size_kib: size.size_kib(),
+            measured: true,
+            ..Default::default()
+        }))
+    } else if options.incremental_only {
+        project.clean_incremental(options.clean.dry_run)
+    } else {
+        project.clean(&options.clean)
+    };
+    let elapsed = started.elapsed();
+    drop(permit);
+    // bytes gone from the target and bytes still in it
+    let verified = size_before.map(|before| {
+        let after = dir_size(&project.target_dir()).bytes;
+        (before.saturating_sub(after), after)
+    });
+    let out = match out {
+        Ok(out) => out,
+        // cargo did clean, only its report is in a format this tool doesn't know
+        Err(CleanError::ParseOutput(output)) => {
+            let first = !std::mem::replace(&mut stats.lock().unwrap().output_unrecognized, true);
+            if first {
+                ui.warn(format!(
+                    "{:>12} unrecognized `cargo clean` output from {}, size figures may be inaccurate, --real-freed measures the disk instead: {}",
+                    style("Warning").bold().yellow(),
+                    options.cargo_version,
+                    summarize(&output)
+                ));
+            }
+            Some(CleanOutput::default())
+        }
+        Err(e) if options.fail_fast => {
+            let e = anyhow::Error::from(e);
+            return Err(e.context(format!("Failed to clean {}", project.path().display())));
+        }
+        Err(e) => {
+            let denied = e.is_permission_denied();
+            {
+                let mut stats = stats.lock().unwrap();
+                if denied {
+                    stats.permission_denied.push(project.path().to_path_buf());
+                } else {
+                    stats.failed += 1;
+                }
+            }
+            if ndjson {
+                Event::ProjectFailed {
+                    path: project.path(),
+                    error: e.to_string(),
+                }
+                .emit(&options.sink)?;
+            }
+            ui.warn(format!(
+                "{:>12} {}: {e}",
+                style(if denied { "Denied" } else { "Failed" }).bold().red(),
+                project.path().display()
+            ));
+            return Ok(());
+        }
+    };
+    // without a required target every crate counts, even if there was nothing to remove
+    let out = match out {
+        None if options.no_target_required => Some(CleanOutput::default()),
+        None => {
+            stats.lock().unwrap().skipped.already_clean += 1;
+            if options.list_already_clean {
+                println(format!(
+                    "{:>12} {}, already clean",
+                    style("Skipped").bold().dim(),
+                    project.path().display()
+                ));
+            }
+            None
+        }
+        out => out,
+    };
+    if let Some(out) = out {
+        // a workspace's members all built into the target just cleaned
+        let crates = project.package_names().len().max(1);
+        let record = {
+            let mut stats = stats.lock().unwrap();
+            stats.record(project.path(), &out);
+            stats.crates += crates;
+            if let Some(profiles) = &profiles {
+                stats.record_profiles(out.size_kib, profiles);
+            }
+            if options.timings
+                && let Some(record) = stats.records.last_mut()
+            {
+                record.elapsed_secs = Some(elapsed.as_secs_f64());
+            }
+            if let Some((freed, _)) = verified
+                && let Some(record) = stats.records.last_mut()
+            {
+                record.verified_freed_kib = Some(freed as f64 / 1024.);
+            }
+            stats.records.last().cloned()
+        };
+        if ndjson && let Some(record) = &record {
+            Event::ProjectCleaned(record).emit(&options.sink)?;
+        }
+
+        let retried = match out.retries {
+            0 => String::new(),
+            1 => " after 1 retry".to_string(),
+            n => format!(" after {n} retries"),
+        };
+        let took = if options.timings {
+            format!(" ({:.2}s)", elapsed.as_secs_f64())
+        } else {
+            String::new()
+        };
+        if out.files > 0 {
+            println(format!(
+                "{:>12} {} files, {} total in {}{retried}{took}",
+                style(options.removed_label()).bold().green(),
+                out.files,
+                options.size_format.format(out.size_kib),
+                project.path().display()
+            ));
+            if let Some(record) = &record
+                && !record.profiles.is_empty()
+            {
+                println(format!(
+                    "{:>12}   {}",
+                    "",
+                    format_profiles(&record.profiles, options.size_format)
+                ));
+            }
+        }
+        if let Some((freed, left)) = verified {
+            println(format!(
+                "{:>12} {} freed on disk, {} reported",
+                style("Verified").bold().green(),
+                options.size_format.format(freed as f64 / 1024.),
+                options.size_format.format(out.size_kib)
+            ));
+            if let Some(max_left) = options.verify
+                && left > max_left
+            {
+                ui.warn(format!(
+                    "{:>12} {} still left in the target of {} after cleaning",
+                    style("Warning").bold().yellow(),
+                    options.size_format.format(left as f64 / 1024.),
+                    project.path().display()
+                ));
+            }
+        }
+
+        if let Some(command) = &options.exec
+            && !options.clean.dry_run
+        {
+            let failure = match project.run_hook(command) {
+                Ok(out) if out.status.success() => None,
+                Ok(out) => Some(format!(
+                    "{}: {}",
+                    out.status,
+                    summarize(&String::from_utf8_lossy(&out.stderr))
+                )),
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(failure) = failure {
+                stats.lock().unwrap().hook_failures += 1;
+                ui.warn(format!(
+                    "{:>12} `{command}` in {}: {failure}",
+                    style("Hook failed").bold().red(),
+                    project.path().display()
+                ));
+            }
+        }
+    }
+
+    if options.prune_empty_target && !options.clean.dry_run {
+        match project.prune_empty_target() {
+            Ok(false) => {}
+            Ok(true) => {
+                stats.lock().unwrap().pruned_targets += 1;
+                println(format!(
+                    "{:>12} empty target in {}",
+                    style("Pruned").bold().green(),
+                    project.path().display()
+                ));
+            }
+            Err(e) => ui.warn(format!(
+                "{:>12} failed to prune target in {}: {e}",
+                style("Warning").bold().yellow(),
+                project.path().display()
+            )),
+        }
+    }
+    // libraries are published without their lockfile, resolving afresh gains nothing
+    if options.delete_lockfile && !options.clean.dry_run && !project.is_library() {
+        match project.delete_lockfile() {
+            Ok(false) => {}
+            Ok(true) => {
+                stats.lock().unwrap().lockfiles_removed += 1;
+                println(format!(
+                    "{:>12} Cargo.lock in {}",
+                    style("Removed").bold().green(),
+                    project.path().display()
+                ));
+            }
+            Err(e) => ui.warn(format!(
+                "{:>12} failed to remove Cargo.lock in {}: {e}",
+                style("Warning").bold().yellow(),
+                project.path().display()
+            )),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    /// A crate with 1KiB of incremental cache.
+    fn fake_project(path: &Path) {
+        let incremental = path.join("target/debug/incremental/crate-1");
+        fs::create_dir_all(&incremental).unwrap();
+        fs::write(incremental.join("query-cache.bin"), [0u8; 1024]).unwrap();
+        fs::write(path.join("Cargo.toml"), "[package]").unwrap();
+    }
+
+    fn dry_incremental() -> ScanOptions {
+        ScanOptions {
+            incremental_only: true,
+            clean: CleanOptions {
+                dry_run: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn age_field_picks_the_timestamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let accessed = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(2_000_000);
+        File::open(dir.path())
+            .unwrap()
+            .set_times(
+                fs::FileTimes::new()
+                    .set_accessed(accessed)
+                    .set_modified(modified),
+            )
+            .unwrap();
+        assert_eq!(age_time(dir.path(), AgeField::Atime).unwrap(), accessed);
+        assert_eq!(age_time(dir.path(), AgeField::Mtime).unwrap(), modified);
+        // setting the times is a status change itself
+        assert!(age_time(dir.path(), AgeField::Ctime).unwrap() > modified);
+    }
+
+    #[test]
+    fn most_specific_rule_decides_the_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let days_ago = |days: u64| SystemTime::now() - Duration::from_secs(days * 24 * 3600);
+        for project in ["scratch/a", "scratch/keep/b", "work/c"] {
+            fake_project(&root.join(project));
+            File::open(root.join(project))
+                .unwrap()
+                .set_modified(days_ago(10))
+                .unwrap();
+        }
+
+        let options = ScanOptions {
+            before: Some(days_ago(90)),
+            rules: vec![
+                (root.join("scratch"), days_ago(3)),
+                (root.join("scratch/keep"), days_ago(30)),
+            ],
+            ..dry_incremental()
+        };
+        let stats = process_directory(&root, &options, &Ui::Silent).unwrap();
+        let cleaned: Vec<_> = stats.records.iter().map(|r| r.path.clone()).collect();
+        assert_eq!(cleaned, [root.join("scratch/a")]);
+        assert_eq!(stats.skipped.too_recent, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deep_mtime_goes_by_the_newest_file_outside_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("crate");
+        fake_project(&project);
+        fs::create_dir(project.join("src")).unwrap();
+        fs::write(project.join("src/main.rs"), "fn main() {}").unwrap();
+        let old = SystemTime::now() - Duration::from_secs(30 * 24 * 3600);
+        for path in ["", "Cargo.toml", "src"] {
+            File::open(project.join(path))
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+
+        let mut options = ScanOptions {
+            before: Some(SystemTime::now() - Duration::from_secs(24 * 3600)),
+            ..dry_incremental()
+        };
+        // the edit to main.rs only shows below the project directory
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 1);
+        options.deep_mtime = true;
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.skipped.too_recent, 1);
+
+        // fresh build output alone doesn't make it recent
+        File::open(project.join("src/main.rs"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_target_is_counted_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"member\"]",
+        )
+        .unwrap();
+        let incremental = root.join("target/debug/incremental/member-1");
+        fs::create_dir_all(&incremental).unwrap();
+        fs::write(incremental.join("query-cache.bin"), [0u8; 1024]).unwrap();
+        fs::create_dir(root.join("member")).unwrap();
+        fs::write(root.join("member/Cargo.toml"), "[package]").unwrap();
+        std::os::unix::fs::symlink(root.join("target"), root.join("member/target")).unwrap();
+
+        let options = ScanOptions {
+            incremental_only: true,
+            clean: CleanOptions {
+                dry_run: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let stats = process_directory(root, &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 1);
+        assert_eq!(stats.files, 1);
+        assert_eq!(stats.size_kib, 1.);
+    }
+
+    #[test]
+    fn package_only_cleans_workspace_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for (project, manifest) in [
+            ("ws", "[workspace]\nmembers = [\"member\"]"),
+            ("ws/member", "[package]"),
+            ("standalone", "[package]"),
+        ] {
+            let project = root.join(project);
+            let incremental = project.join("target/debug/incremental/crate-1");
+            fs::create_dir_all(&incremental).unwrap();
+            fs::write(incremental.join("query-cache.bin"), [0u8; 1024]).unwrap();
+            fs::write(project.join("Cargo.toml"), manifest).unwrap();
+        }
+
+        let options = ScanOptions {
+            incremental_only: true,
+            clean: CleanOptions {
+                packages: vec!["member".to_owned()],
+                dry_run: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let stats = process_directory(root, &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 1);
+        assert_eq!(stats.records[0].path, root.join("ws"));
+        assert_eq!(stats.skipped.not_workspace_root, 2);
+        assert!(
+            stats.skipped.to_string().contains("2 not a workspace root"),
+            "{}",
+            stats.skipped
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn project_reached_through_a_symlink_is_cleaned_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fake_project(&root.join("real"));
+        std::os::unix::fs::symlink(root.join("real"), root.join("alias")).unwrap();
+
+        let stats = process_directory(root, &dry_incremental(), &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 1);
+        assert_eq!(stats.records.len(), 1);
+        assert_eq!(stats.skipped.shared_target, 1);
+        assert_eq!(stats.size_kib, 1.);
+
+        // the alias isn't measured again for --min-size
+        let options = ScanOptions {
+            min_size: Some(4096),
+            ..dry_incremental()
+        };
+        let stats = process_directory(root, &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 0);
+        assert_eq!(stats.skipped.too_small, 1);
+        assert_eq!(stats.skipped.shared_target, 1);
+    }
+
+    #[test]
+    fn workspace_members_count_as_crates() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("workspace");
+        fake_project(&root);
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]",
+        )
+        .unwrap();
+        for name in ["core", "cli"] {
+            fs::create_dir_all(root.join("crates").join(name)).unwrap();
+            fs::write(
+                root.join("crates").join(name).join("Cargo.toml"),
+                format!("[package]\nname = \"{name}\""),
+            )
+            .unwrap();
+        }
+        fake_project(&dir.path().join("single"));
+
+        let stats = process_directory(dir.path(), &dry_incremental(), &Ui::Silent).unwrap();
+        assert_eq!((stats.projects, stats.crates), (2, 3));
+        assert!(
+            stats
+                .to_string()
+                .contains(", 3 crates in 2 target directories"),
+            "{stats}"
+        );
+    }
+
+    #[test]
+    fn no_nested_skips_projects_inside_projects() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fake_project(root);
+        fake_project(&root.join("tests/fixtures/inner"));
+
+        let mut options = dry_incremental();
+        let stats = process_directory(root, &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 2);
+
+        options.no_nested = true;
+        let stats = process_directory(root, &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 1);
+        assert_eq!(stats.records[0].path, root);
+
+        options.parallel_walk = true;
+        let stats = process_directory(root, &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 1);
+        assert_eq!(stats.records[0].path, root);
+    }
+
+    #[test]
+    fn workspace_members_can_be_left_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fake_project(&root.join("ws"));
+        fs::write(
+            root.join("ws/Cargo.toml"),
+            "[workspace]\nmembers = [\"member\"]",
+        )
+        .unwrap();
+        // a target left over from before the crate joined the workspace
+        fake_project(&root.join("ws/member"));
+        fake_project(&root.join("standalone"));
+
+        let mut options = dry_incremental();
+        let stats = process_directory(root, &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 3);
+
+        options.exclude_workspace_members = true;
+        let stats = process_directory(root, &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 2);
+        assert_eq!(stats.skipped.excluded, 1);
+        assert!(stats.records.iter().all(|r| !r.path.ends_with("member")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keep_latest_spares_the_newest_projects() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        for (name, age_days) in [("old", 30), ("new", 1), ("older", 60)] {
+            let path = dir.path().join(name);
+            fake_project(&path);
+            let age = Duration::from_secs(age_days * 24 * 60 * 60);
+            fs::File::open(&path)
+                .unwrap()
+                .set_modified(now - age)
+                .unwrap();
+        }
+
+        let options = ScanOptions {
+            keep_latest: Some(2),
+            ..dry_incremental()
+        };
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.kept_latest, 2);
+        assert_eq!(stats.projects, 1);
+        assert_eq!(stats.records[0].path, dir.path().join("older"));
+
+        // the newest projects are kept even when --past already spares them
+        let options = ScanOptions {
+            keep_latest: Some(2),
+            before: Some(now - Duration::from_secs(7 * 24 * 60 * 60)),
+            ..dry_incremental()
+        };
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.kept_latest, 2);
+        assert_eq!(stats.skipped.too_recent, 0);
+        assert_eq!(stats.projects, 1);
+        assert_eq!(stats.records[0].path, dir.path().join("older"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn access_time_filters_combine() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        let days = |n: u64| now - Duration::from_secs(n * 24 * 60 * 60);
+        for (name, accessed) in [("idle", 40), ("read", 10), ("fresh", 1)] {
+            let path = dir.path().join(name);
+            fake_project(&path);
+            let times = fs::FileTimes::new()
+                .set_accessed(days(accessed))
+                .set_modified(days(60));
+            fs::File::open(&path).unwrap().set_times(times).unwrap();
+        }
+
+        let options = ScanOptions {
+            before: Some(days(30)),
+            accessed_before: Some(days(5)),
+            accessed_after: Some(days(20)),
+            ..dry_incremental()
+        };
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 1);
+        assert_eq!(stats.records[0].path, dir.path().join("read"));
+        assert_eq!(stats.skipped.too_recent, 1);
+        assert_eq!(stats.skipped.too_old, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn older_than_accessed_follows_the_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let now = SystemTime::now();
+        let days = |n: u64| now - Duration::from_secs(n * 24 * 60 * 60);
+        for project in ["scratch/a", "work/b"] {
+            let path = root.join(project);
+            fake_project(&path);
+            let times = fs::FileTimes::new()
+                .set_accessed(days(10))
+                .set_modified(days(60));
+            File::open(&path).unwrap().set_times(times).unwrap();
+        }
+
+        let options = ScanOptions {
+            before: Some(days(5)),
+            rules: vec![(root.join("work"), days(30))],
+            older_than_accessed: true,
+            ..dry_incremental()
+        };
+        let stats = process_directory(&root, &options, &Ui::Silent).unwrap();
+        let cleaned: Vec<_> = stats.records.iter().map(|r| r.path.clone()).collect();
+        assert_eq!(cleaned, [root.join("scratch/a")]);
+        assert_eq!(stats.skipped.too_recent, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sort_orders_the_projects() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        for (name, age_days, kib) in [("b", 1, 2), ("a", 2, 1), ("c", 3, 4)] {
+            let path = dir.path().join(name);
+            fake_project(&path);
+            let cache = path.join("target/debug/incremental/crate-1/query-cache.bin");
+            fs::write(cache, vec![0u8; kib * 1024]).unwrap();
+            let age = Duration::from_secs(age_days * 24 * 60 * 60);
+            fs::File::open(&path)
+                .unwrap()
+                .set_modified(now - age)
+                .unwrap();
+        }
+
+        for (sort, expected) in [
+            (SortOrder::Path, ["a", "b", "c"]),
+            (SortOrder::Size, ["c", "b", "a"]),
+            (SortOrder::Mtime, ["c", "a", "b"]),
+        ] {
+            let options = ScanOptions {
+                sort: Some(sort),
+                ..dry_incremental()
+            };
+            let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+            let cleaned: Vec<_> = stats.records.iter().map(|r| r.path.clone()).collect();
+            assert_eq!(cleaned, expected.map(|name| dir.path().join(name)));
+        }
+    }
+
+    #[test]
+    fn stable_order_prints_the_lines_in_queue_order() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["d", "b", "a", "c"] {
+            fake_project(&dir.path().join(name));
+        }
+
+        let run = |options: ScanOptions| {
+            let log = dir.path().join("log");
+            let ui = Ui::tee(Ui::Silent, &log).unwrap();
+            process_directory(dir.path(), &options, &ui).unwrap();
+            ui.flush().unwrap();
+            let log = fs::read_to_string(&log).unwrap();
+            fs::remove_file(dir.path().join("log")).unwrap();
+            log.lines()
+                .filter_map(|line| line.rsplit('/').next().map(str::to_owned))
+                .collect::<Vec<_>>()
+        };
+        // the walk order, whatever the filesystem makes of it
+        let single = run(dry_incremental());
+        assert_eq!(single.len(), 4, "{single:?}");
+        let held = run(ScanOptions {
+            jobs: 4,
+            stable_order: true,
+            ..dry_incremental()
+        });
+        assert_eq!(held, single);
+        let sorted = run(ScanOptions {
+            jobs: 4,
+            stable_order: true,
+            sort: Some(SortOrder::Path),
+            ..dry_incremental()
+        });
+        assert_eq!(sorted, ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn verify_measures_what_cleaning_freed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("crate");
+        fake_project(&root);
+        let cache = root.join("target/debug/incremental/crate-1/query-cache.bin");
+        fs::write(cache, vec![0u8; 2048]).unwrap();
+
+        let options = ScanOptions {
+            verify: Some(0),
+            incremental_only: true,
+            ..Default::default()
+        };
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.records[0].verified_freed_kib, Some(2.));
+    }
+
+    #[test]
+    fn scan_only_lists_the_projects_without_cleaning() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b", "a"] {
+            fake_project(&dir.path().join("src").join(name));
+        }
+
+        let list = dir.path().join("list");
+        let options = ScanOptions {
+            scan_only: true,
+            sort: Some(SortOrder::Path),
+            sink: Sink::new(Some(&list)).unwrap(),
+            // would fail if it were run
+            clean: CleanOptions {
+                cargo: dir.path().join("no-cargo"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let stats = process_directory(&dir.path().join("src"), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 0);
+        let list = fs::read_to_string(list).unwrap();
+        let a = dir.path().join("src/a");
+        let b = dir.path().join("src/b");
+        assert_eq!(list, format!("{}\n{}\n", a.display(), b.display()));
+        assert!(a.join("target/debug/incremental").exists());
+    }
+
+    #[test]
+    fn semaphore_bounds_concurrency() {
+        let io = Semaphore::new(2);
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..6 {
+                scope.spawn(|| {
+                    let _permit = io.acquire();
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(std::time::Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn too_few_projects_abort_the_run() {
+        let dir = tempfile::tempdir().unwrap();
+        fake_project(&dir.path().join("only"));
+
+        let mut options = ScanOptions {
+            min_projects: Some(2),
+            ..dry_incremental()
+        };
+        assert!(process_directory(dir.path(), &options, &Ui::Silent).is_err());
+        options.min_projects = Some(1);
+        assert!(process_directory(dir.path(), &options, &Ui::Silent).is_ok());
+    }
+
+    #[test]
+    fn targets_above_fail_on_size_are_noted() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, kib) in [("small", 1), ("large", 4)] {
+            let path = dir.path().join(name);
+            fake_project(&path);
+            let cache = path.join("target/debug/incremental/crate-1/query-cache.bin");
+            fs::write(cache, vec![0u8; kib * 1024]).unwrap();
+        }
+
+        let options = ScanOptions {
+            fail_on_size: Some(2 * 1024),
+            ..dry_incremental()
+        };
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.oversized.len(), 1);
+        assert_eq!(stats.oversized[0].0, dir.path().join("large"));
+        assert!(stats.oversized[0].1 >= 4 * 1024);
+        // noting them doesn't keep them from being cleaned
+        assert_eq!(stats.projects, 2);
+    }
+
+    #[test]
+    fn free_at_least_cleans_the_largest_projects_until_the_budget_is_met() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, kib) in [("small", 1), ("large", 4), ("medium", 2)] {
+            let path = dir.path().join(name);
+            fake_project(&path);
+            let cache = path.join("target/debug/incremental/crate-1/query-cache.bin");
+            fs::write(cache, vec![0u8; kib * 1024]).unwrap();
+        }
+
+        let options = ScanOptions {
+            free_at_least: Some(5 * 1024),
+            ..dry_incremental()
+        };
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        let cleaned: Vec<_> = stats.records.iter().map(|r| r.path.clone()).collect();
+        assert_eq!(
+            cleaned,
+            [dir.path().join("large"), dir.path().join("medium")]
+        );
+        assert_eq!(stats.skipped.over_budget, 1);
+        assert!(stats.to_string().contains("budget of 5.00KiB met"));
+    }
+
+    #[test]
+    fn parallel_jobs_clean_every_project() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..5 {
+            fake_project(&dir.path().join(format!("crate-{i}")));
+        }
+
+        let options = ScanOptions {
+            jobs: 3,
+            ..dry_incremental()
+        };
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 5);
+        assert_eq!(stats.size_kib, 5.);
+        assert!(stats.to_string().ends_with("(measured)"));
+    }
+
+    #[test]
+    fn parallel_walk_finds_the_same_projects() {
+        let dir = tempfile::tempdir().unwrap();
+        for path in ["a", "b/c", ".hidden/d", "b/c/e"] {
+            fake_project(&dir.path().join(path));
+        }
+
+        let sequential = process_directory(dir.path(), &dry_incremental(), &Ui::Silent).unwrap();
+        let options = ScanOptions {
+            parallel_walk: true,
+            ..dry_incremental()
+        };
+        let parallel = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        let paths = |stats: &CleanupStats| {
+            let mut paths: Vec<_> = stats.records.iter().map(|r| r.path.clone()).collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(sequential.projects, 4);
+        assert_eq!(paths(&sequential), paths(&parallel));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unrecognized_cargo_output_still_counts_the_project() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cargo = dir.path().join("cargo");
+        fs::write(&cargo, "#!/bin/sh\necho 'Deleted everything' >&2\n").unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+        fake_project(&dir.path().join("crate"));
+
+        let options = ScanOptions {
+            clean: CleanOptions {
+                cargo,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 1);
+        assert_eq!(stats.failed, 0);
+        assert!(stats.output_unrecognized);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permission_errors_are_told_apart_from_failures() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = tempfile::tempdir().unwrap();
+        let cargo = bin.path().join("cargo");
+        fs::write(
+            &cargo,
+            "#!/bin/sh
+if [ -e broken ]; then echo 'error: failed to parse manifest' >&2; exit 101; fi
+             echo 'error: failed to remove file `target/debug/app`' >&2
+             echo 'Caused by: Permission denied (os error 13)' >&2
+exit 101
+",
+        )
+        .unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        fake_project(&dir.path().join("shared"));
+        fake_project(&dir.path().join("broken"));
+        fs::write(dir.path().join("broken/broken"), "").unwrap();
+
+        let options = ScanOptions {
+            clean: CleanOptions {
+                cargo,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let stats = process_directory(dir.path(), &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.permission_denied, [dir.path().join("shared")]);
+        assert_eq!(stats.failed, 1);
+        assert!(
+            stats
+                .to_string()
+                .contains(", 1 failed, 1 permission denied"),
+            "{stats}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn already_clean_projects_are_listed_when_asked() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cargo = dir.path().join("cargo");
+        fs::write(&cargo, "#!/bin/sh\necho '     Removed 0 files' >&2\n").unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+        fake_project(&dir.path().join("crate"));
+
+        let log = dir.path().join("log");
+        let ui = Ui::tee(Ui::Silent, &log).unwrap();
+        let options = ScanOptions {
+            list_already_clean: true,
+            clean: CleanOptions {
+                cargo,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let stats = process_directory(dir.path(), &options, &ui).unwrap();
+        ui.flush().unwrap();
+        assert_eq!(stats.projects, 0);
+        assert_eq!(stats.skipped.already_clean, 1);
+        let log = fs::read_to_string(log).unwrap();
+        assert!(log.contains("crate, already clean"), "{log}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn interrupt_finishes_the_running_clean_and_starts_no_more() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = tempfile::tempdir().unwrap();
+        let started = bin.path().join("started");
+        let go = bin.path().join("go");
+        let cargo = bin.path().join("cargo");
+        fs::write(
+            &cargo,
+            format!(
+                "#!/bin/sh\ntouch {}\nwhile [ ! -e {} ]; do sleep 0.01; done\n\
+                 echo '     Removed 1 file, 1.0KiB total' >&2\n",
+                started.display(),
+                go.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        fake_project(&dir.path().join("a"));
+        fake_project(&dir.path().join("b"));
+
+        let options = ScanOptions {
+            clean: CleanOptions {
+                cargo,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let stats = thread::scope(|scope| {
+            let interrupted = &options.interrupted;
+            scope.spawn(move || {
+                while !started.exists() {
+                    thread::sleep(std::time::Duration::from_millis(10));
+                }
+                interrupted.store(true, Ordering::Relaxed);
+                fs::write(go, "").unwrap();
+            });
+            process_directory(dir.path(), &options, &Ui::Silent).unwrap()
+        });
+        assert!(stats.interrupted);
+        assert_eq!(stats.projects, 1);
+        assert_eq!(stats.skipped.interrupted, 1);
+        assert_eq!(stats.skipped.over_budget, 0);
+    }
+
+    #[test]
+    fn single_project_ignores_everything_below() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fake_project(root);
+        fake_project(&root.join("examples/demo"));
+
+        let options = ScanOptions {
+            single_project: true,
+            ..dry_incremental()
+        };
+        let stats = process_directory(root, &options, &Ui::Silent).unwrap();
+        assert_eq!(stats.projects, 1);
+        assert_eq!(stats.records[0].path, root);
+    }
+
+    #[test]
+    fn cargocleanignore_skips_the_project_and_its_members() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for path in ["kept", "ignored", "ignored/member"] {
+            fake_project(&root.join(path));
+        }
+        fs::write(root.join("ignored/.cargocleanignore"), "").unwrap();
+
+        for parallel_walk in [false, true] {
+            let options = ScanOptions {
+                parallel_walk,
+                ..dry_incremental()
+            };
+            let stats = process_directory(root, &options, &Ui::Silent).unwrap();
+            assert_eq!(stats.projects, 1);
+            assert_eq!(stats.records[0].path, root.join("kept"));
+            assert_eq!(stats.skipped.ignored, 1);
+        }
+
+        // listed like the already clean ones when asked
+        let log = root.join("log");
+        let ui = Ui::tee(Ui::Silent, &log).unwrap();
+        let options = ScanOptions {
+            list_already_clean: true,
+            ..dry_incremental()
+        };
+        process_directory(root, &options, &ui).unwrap();
+        ui.flush().unwrap();
+        let log = fs::read_to_string(log).unwrap();
+        let ignored = format!("Ignored {}", root.join("ignored").display());
+        assert!(log.contains(&ignored), "{log}");
+        assert!(!log.contains("member"), "{log}");
+    }
+
+    #[test]
+    fn skip_submodules_prunes_only_listed_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for path in ["app", "vendor/dep", "vendor/own", "tools/repo/sub"] {
+            fake_project(&root.join(path));
+        }
+        fs::write(
+            root.join(".gitmodules"),
+            "[submodule \"dep\"]\n\tpath = vendor/dep\n\turl = https://example.com/dep.git\n",
+        )
+        .unwrap();
+        // a nested repository with its own submodules
+        fs::write(
+            root.join("tools/repo/.gitmodules"),
+            "[submodule \"sub\"]\npath=sub\n",
+        )
+        .unwrap();
+
+        for parallel_walk in [false, true] {
+            let options = ScanOptions {
+                skip_submodules: true,
+                parallel_walk,
+                ..dry_incremental()
+            };
+            let stats = process_directory(root, &options, &Ui::Silent).unwrap();
+            let mut paths: Vec<_> = stats.records.iter().map(|r| r.path.clone()).collect();
+            paths.sort();
+            assert_eq!(paths, [root.join("app"), root.join("vendor/own")]);
+        }
+    }
+}
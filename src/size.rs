@@ -0,0 +1,179 @@
+use crate::stats::SizeFormat;
+use anyhow::bail;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Number of files and their total size below a directory.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DirSize {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl DirSize {
+    pub fn size_kib(&self) -> f64 {
+        self.bytes as f64 / 1024.
+    }
+}
+
+/// Sum the sizes of all regular files below `path`, unreadable entries are ignored.
+pub fn dir_size(path: &Path) -> DirSize {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .fold(DirSize::default(), |acc, m| DirSize {
+            files: acc.files + 1,
+            bytes: acc.bytes + m.len(),
+        })
+}
+
+/// Bytes below a target directory grouped by profile.
+///
+/// A profile directory is recognised by cargo's `deps` or `.fingerprint`
+/// bookkeeping, which covers `debug`, `release` and custom profiles alike, both
+/// as `target/<profile>` and, when cross compiling, `target/<triple>/<profile>`.
+/// `target/doc` is reported as `doc`, everything else as `other`.
+pub fn profile_sizes(target: &Path) -> BTreeMap<String, u64> {
+    let mut is_profile = HashMap::new();
+    let mut is_profile_dir = |dir: PathBuf| {
+        *is_profile.entry(dir).or_insert_with_key(|dir: &PathBuf| {
+            dir.join("deps").is_dir() || dir.join(".fingerprint").is_dir()
+        })
+    };
+
+    let mut sizes = BTreeMap::new();
+    for entry in WalkDir::new(target)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        let Ok(len) = entry.metadata().map(|m| m.len()) else {
+            continue;
+        };
+        let relative = entry.path().strip_prefix(target).unwrap_or(entry.path());
+        let mut components = relative.components().map(|c| c.as_os_str());
+        let profile = match (components.next(), components.next(), components.next()) {
+            (Some(first), Some(_), _) if first == "doc" => "doc".to_string(),
+            (Some(first), Some(_), _) if is_profile_dir(target.join(first)) => {
+                first.to_string_lossy().into_owned()
+            }
+            (Some(first), Some(second), Some(_))
+                if is_profile_dir(target.join(first).join(second)) =>
+            {
+                second.to_string_lossy().into_owned()
+            }
+            _ => "other".to_string(),
+        };
+        *sizes.entry(profile).or_default() += len;
+    }
+    sizes
+}
+
+/// Render a per-profile breakdown as `debug: 3.20GiB, release: 800.00MiB`.
+pub fn format_profiles(profiles: &BTreeMap<String, f64>, size_format: SizeFormat) -> String {
+    profiles
+        .iter()
+        .map(|(profile, size_kib)| format!("{profile}: {}", size_format.format(*size_kib)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Parse a size such as `512B`, `1.5GiB` or `200MB` into bytes.
+///
+/// Units are case-insensitive, `KiB`/`MiB`/`GiB`/`TiB` are powers of 1024 while
+/// `KB`/`MB`/`GB`/`TB` are powers of 1000. A bare number is taken as bytes.
+pub fn parse_size(input: &str) -> anyhow::Result<u64> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let (value, unit) = input.split_at(split);
+    if value.is_empty() || value.starts_with('.') || value.ends_with('.') {
+        bail!("Invalid size `{input}`, expected a number followed by a unit");
+    }
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000_u64.pow(2),
+        "gb" => 1000_u64.pow(3),
+        "tb" => 1000_u64.pow(4),
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => bail!(
+            "Unknown size unit in `{input}`, available units: B, KB, MB, GB, TB, KiB, MiB, GiB, TiB"
+        ),
+    };
+    let value = value
+        .parse::<f64>()
+        .map_err(|_| anyhow::anyhow!("Invalid number in size `{input}`"))?;
+    let bytes = value * multiplier as f64;
+    if bytes >= u64::MAX as f64 {
+        bail!("Size `{input}` is too large");
+    }
+    Ok(bytes.round() as u64)
+}
+
+/// Parse a percentage such as `90` or `87.5%`.
+pub fn parse_percent(input: &str) -> anyhow::Result<f64> {
+    let input = input.trim();
+    let percent = input
+        .strip_suffix('%')
+        .unwrap_or(input)
+        .parse::<f64>()
+        .map_err(|_| anyhow::anyhow!("Invalid percentage `{input}`, expected e.g. `90%`"))?;
+    if !(0. ..=100.).contains(&percent) {
+        bail!("Invalid percentage `{input}`, it must be between 0 and 100");
+    }
+    Ok(percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentages() {
+        assert_eq!(parse_percent("90").unwrap(), 90.);
+        assert_eq!(parse_percent("87.5%").unwrap(), 87.5);
+        assert!(parse_percent("120%").is_err());
+        assert!(parse_percent("lots").is_err());
+    }
+
+    #[test]
+    fn binary_units() {
+        assert_eq!(parse_size("512B").unwrap(), 512);
+        assert_eq!(parse_size("1KiB").unwrap(), 1024);
+        assert_eq!(parse_size("1.5MiB").unwrap(), 1536 * 1024);
+        assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1TiB").unwrap(), 1 << 40);
+    }
+
+    #[test]
+    fn decimal_units() {
+        assert_eq!(parse_size("1KB").unwrap(), 1000);
+        assert_eq!(parse_size("2.5MB").unwrap(), 2_500_000);
+        assert_eq!(parse_size("1GB").unwrap(), 1_000_000_000);
+        assert_eq!(parse_size("1TB").unwrap(), 1_000_000_000_000);
+    }
+
+    #[test]
+    fn units_are_case_insensitive() {
+        assert_eq!(parse_size("1gib").unwrap(), 1 << 30);
+        assert_eq!(parse_size("1Gb").unwrap(), 1_000_000_000);
+        assert_eq!(parse_size("100").unwrap(), 100);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("GiB").is_err());
+        assert!(parse_size("1.2.3MB").is_err());
+        assert!(parse_size("1PB").is_err());
+        assert!(parse_size("-1MB").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::size::{DirSize, dir_size};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+/// Target directory sizes remembered between `--stats-only` runs.
+///
+/// An entry is reused as long as no directory up to two levels below the target, e.g.
+/// `target/debug/deps`, was modified since it was measured. Builds add and replace files
+/// there, edits deeper down without touching those directories go unnoticed.
+pub struct SizeCache {
+    path: PathBuf,
+    /// Measure every target again, only updating the cache.
+    refresh: bool,
+    entries: Mutex<HashMap<PathBuf, CachedSize>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct CachedSize {
+    modified: SystemTime,
+    files: usize,
+    bytes: u64,
+}
+
+impl SizeCache {
+    /// Load the cache from the platform cache directory, starting empty when it doesn't
+    /// exist yet or can't be read.
+    pub fn load(refresh: bool) -> Option<Self> {
+        let path = dirs::cache_dir()?
+            .join("cargo-clean-plus")
+            .join("target-sizes.json");
+        let entries = fs::read(&path)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default();
+        Some(Self {
+            path,
+            refresh,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Size of `target`, from the cache when it is still up to date.
+    pub fn dir_size(&self, target: &Path) -> DirSize {
+        let Some(modified) = last_modified(target) else {
+            return dir_size(target);
+        };
+        if !self.refresh
+            && let Some(cached) = self.entries.lock().unwrap().get(target)
+            && cached.modified >= modified
+        {
+            return DirSize {
+                files: cached.files,
+                bytes: cached.bytes,
+            };
+        }
+        let size = dir_size(target);
+        self.entries.lock().unwrap().insert(
+            target.to_path_buf(),
+            CachedSize {
+                modified,
+                files: size.files,
+                bytes: size.bytes,
+            },
+        );
+        size
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let entries = self.entries.lock().unwrap();
+        // forget targets that have been removed since
+        let entries: HashMap<_, _> = entries.iter().filter(|(path, _)| path.is_dir()).collect();
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, serde_json::to_vec(&entries)?)?;
+        Ok(())
+    }
+}
+
+/// The newest modification time of `target` and the directories up to two levels below.
+fn last_modified(target: &Path) -> Option<SystemTime> {
+    WalkDir::new(target)
+        .max_depth(2)
+        .into_iter()
+        .filter_entry(|e| e.file_type().is_dir())
+        .filter_map(|e| e.ok()?.metadata().ok()?.modified().ok())
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_size_is_reused_until_the_target_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::create_dir_all(target.join("debug/deps")).unwrap();
+        fs::write(target.join("debug/deps/liba.rlib"), [0u8; 1024]).unwrap();
+        let cache = SizeCache {
+            path: dir.path().join("cache.json"),
+            refresh: false,
+            entries: Mutex::new(HashMap::new()),
+        };
+        assert_eq!(cache.dir_size(&target).bytes, 1024);
+
+        // a stale entry is served as long as the directories look untouched
+        cache
+            .entries
+            .lock()
+            .unwrap()
+            .get_mut(&target)
+            .unwrap()
+            .bytes = 1;
+        assert_eq!(cache.dir_size(&target).bytes, 1);
+
+        cache
+            .entries
+            .lock()
+            .unwrap()
+            .get_mut(&target)
+            .unwrap()
+            .modified = SystemTime::UNIX_EPOCH;
+        assert_eq!(cache.dir_size(&target).bytes, 1024);
+
+        cache.save().unwrap();
+        let saved: HashMap<PathBuf, CachedSize> =
+            serde_json::from_slice(&fs::read(dir.path().join("cache.json")).unwrap()).unwrap();
+        assert_eq!(saved[&target].files, 1);
+    }
+}
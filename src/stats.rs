@@ -0,0 +1,497 @@
+use crate::project::CleanOutput;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Add, AddAssign};
+use std::path::{Path, PathBuf};
+
+/// How sizes are printed, chosen with `--bytes` or `--human`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SizeFormat {
+    /// KiB, MiB or GiB with two decimals.
+    #[default]
+    Human,
+    /// A whole number of bytes without a unit, for scripts.
+    Bytes,
+}
+
+impl SizeFormat {
+    pub fn format(self, size_kib: f64) -> String {
+        match self {
+            SizeFormat::Bytes => ((size_kib * 1024.).round() as u64).to_string(),
+            SizeFormat::Human if size_kib > 1024. * 1024. => {
+                format!("{:.2}GiB", size_kib / 1024. / 1024.)
+            }
+            SizeFormat::Human if size_kib > 1024. => format!("{:.2}MiB", size_kib / 1024.),
+            SizeFormat::Human => format!("{:.2}KiB", size_kib),
+        }
+    }
+}
+
+/// What was removed from a single project.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectRecord {
+    pub path: PathBuf,
+    pub files: usize,
+    pub size_kib: f64,
+    /// Freed KiB per profile, only filled with `--by-profile`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, f64>,
+    /// How long cleaning took in seconds, only filled with `--timings`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed_secs: Option<f64>,
+    /// How much the target shrank on disk, only filled with `--verify`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified_freed_kib: Option<f64>,
+}
+
+/// Totals accumulated over a whole run.
+#[derive(Debug, Default, Clone)]
+pub struct CleanupStats {
+    /// Cleaned projects, each with a target directory of its own.
+    pub projects: usize,
+    /// Crates whose build output went with those targets, counting every member of a
+    /// cleaned workspace.
+    pub crates: usize,
+    pub files: usize,
+    pub size_kib: f64,
+    /// Projects where `cargo clean` failed.
+    pub failed: usize,
+    /// Projects whose target couldn't be cleaned for lack of permissions, not counted in
+    /// `failed`.
+    pub permission_denied: Vec<PathBuf>,
+    /// Whether Ctrl-C stopped the run before every project was cleaned.
+    pub interrupted: bool,
+    /// Cleaned projects whose size was measured on disk instead of reported by cargo.
+    pub measured: usize,
+    /// Projects spared by `--keep-latest`.
+    pub kept_latest: usize,
+    /// Empty target directories removed by `--prune-empty-target`.
+    pub pruned_targets: usize,
+    /// Lockfiles removed by `--delete-lockfile`.
+    pub lockfiles_removed: usize,
+    /// Cleaned projects where the `--exec` command failed.
+    pub hook_failures: usize,
+    /// What was removed from the sccache directory, if it was cleared.
+    pub sccache: Option<CleanOutput>,
+    /// What was removed from cargo's download caches with `--include-cargo-cache`.
+    pub cargo_cache: Option<CleanOutput>,
+    /// Every cleaned project, in the order they were cleaned.
+    pub records: Vec<ProjectRecord>,
+    /// Freed KiB attributed to each profile, only filled with `--by-profile`.
+    pub profiles: BTreeMap<String, f64>,
+    /// How much the free space on the scanned filesystem grew over the run, with
+    /// `--real-freed`. Negative when something else filled the disk meanwhile.
+    pub disk_freed_kib: Option<f64>,
+    /// Whether some `cargo clean` output couldn't be parsed, leaving those projects'
+    /// sizes out of the totals.
+    pub output_unrecognized: bool,
+    /// Bytes to free with `--free-at-least`.
+    pub budget: Option<u64>,
+    /// Projects whose target was larger than `--fail-on-size`, with its size in bytes.
+    pub oversized: Vec<(PathBuf, u64)>,
+    pub skipped: SkipTally,
+    /// How the sizes in `totals` and the summary are printed.
+    pub size_format: SizeFormat,
+}
+
+/// Why directories met during the scan weren't cleaned.
+#[derive(Debug, Default, Clone)]
+pub struct SkipTally {
+    /// Directories without a `Cargo.toml`.
+    pub no_manifest: usize,
+    /// Crates without a target directory.
+    pub no_target: usize,
+    /// Projects modified or accessed within `--past`.
+    pub too_recent: usize,
+    /// Projects not modified within `--newer-than`.
+    pub too_old: usize,
+    /// Projects left out by `--include`, `--exclude` or `--exclude-name`.
+    pub excluded: usize,
+    /// Standalone crates and workspace members, which `--package` leaves alone.
+    pub not_workspace_root: usize,
+    /// Projects below `--min-size`.
+    pub too_small: usize,
+    /// Projects sharing a target directory that was already handled.
+    pub shared_target: usize,
+    /// Projects where there was nothing to remove.
+    pub already_clean: usize,
+    /// Projects left once the `--free-at-least` budget was met.
+    pub over_budget: usize,
+    /// Projects opted out with a `.cargocleanignore` file.
+    pub ignored: usize,
+    /// Projects left once Ctrl-C was pressed.
+    pub interrupted: usize,
+}
+
+impl CleanupStats {
+    pub fn record(&mut self, path: &Path, out: &CleanOutput) {
+        self.projects += 1;
+        self.measured += usize::from(out.measured);
+        self.files += out.files;
+        self.size_kib += out.size_kib;
+        self.records.push(ProjectRecord {
+            path: path.to_path_buf(),
+            files: out.files,
+            size_kib: out.size_kib,
+            profiles: BTreeMap::new(),
+            elapsed_secs: None,
+            verified_freed_kib: None,
+        });
+    }
+
+    /// Split `freed_kib` of the last recorded project across profiles in proportion
+    /// to their size before cleaning.
+    pub fn record_profiles(&mut self, freed_kib: f64, sizes: &BTreeMap<String, u64>) {
+        let total = sizes.values().sum::<u64>();
+        if total == 0 {
+            return;
+        }
+        for (profile, &bytes) in sizes {
+            let share = freed_kib * bytes as f64 / total as f64;
+            *self.profiles.entry(profile.clone()).or_default() += share;
+            if let Some(record) = self.records.last_mut() {
+                record.profiles.insert(profile.clone(), share);
+            }
+        }
+    }
+
+    /// Freed KiB per directory `depth` levels below `base`, largest first. Projects less deep
+    /// than that count under their own path, those outside `base` under their full path.
+    pub fn grouped(&self, base: &Path, depth: usize) -> Vec<(PathBuf, f64)> {
+        let mut groups = BTreeMap::<PathBuf, f64>::new();
+        for record in &self.records {
+            let group = match record.path.strip_prefix(base) {
+                Ok(relative) => relative.components().take(depth).collect(),
+                Err(_) => record.path.clone(),
+            };
+            *groups.entry(group).or_default() += record.size_kib;
+        }
+        let mut groups: Vec<_> = groups.into_iter().collect();
+        groups.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        groups
+    }
+
+    /// The `n` projects that took longest to clean, slowest first.
+    pub fn slowest(&self, n: usize) -> Vec<&ProjectRecord> {
+        let mut timed: Vec<_> = self
+            .records
+            .iter()
+            .filter_map(|record| Some((record.elapsed_secs?, record)))
+            .collect();
+        timed.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        timed
+            .into_iter()
+            .take(n)
+            .map(|(_, record)| record)
+            .collect()
+    }
+
+    /// The headline numbers only: projects, files and size.
+    pub fn totals(&self) -> String {
+        format!(
+            "{} projects, {} files, {} total",
+            self.projects,
+            self.files,
+            self.format_size(self.size_kib)
+        )
+    }
+
+    /// `, 2 failed, 1 permission denied` for the projects that couldn't be cleaned, empty
+    /// when there are none.
+    pub fn failures(&self) -> String {
+        let mut failures = String::new();
+        if self.failed > 0 {
+            failures.push_str(&format!(", {} failed", self.failed));
+        }
+        if !self.permission_denied.is_empty() {
+            let denied = self.permission_denied.len();
+            failures.push_str(&format!(", {denied} permission denied"));
+        }
+        failures
+    }
+
+    pub fn format_size(&self, size_kib: f64) -> String {
+        self.size_format.format(size_kib)
+    }
+}
+
+impl fmt::Display for SkipTally {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reasons = [
+            (self.no_manifest, "without Cargo.toml"),
+            (self.no_target, "without target"),
+            (self.too_recent, "too recent"),
+            (self.too_old, "too old"),
+            (self.excluded, "excluded"),
+            (self.not_workspace_root, "not a workspace root"),
+            (self.ignored, "with .cargocleanignore"),
+            (self.too_small, "too small"),
+            (self.shared_target, "sharing a target"),
+            (self.already_clean, "already clean"),
+            (self.over_budget, "not needed for the budget"),
+            (self.interrupted, "left after the interrupt"),
+        ];
+        let reasons: Vec<_> = reasons
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, reason)| format!("{count} {reason}"))
+            .collect();
+        if reasons.is_empty() {
+            write!(f, "nothing")
+        } else {
+            write!(f, "{}", reasons.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for CleanupStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.totals())?;
+        // runs that measure themselves and those taking cargo's word aren't comparable
+        let mut notes = match self.measured {
+            _ if self.projects == 0 => vec![],
+            0 => vec!["reported by cargo".to_owned()],
+            n if n == self.projects => vec!["measured".to_owned()],
+            n => vec![format!("{n} measured, the rest reported by cargo")],
+        };
+        if self.output_unrecognized {
+            notes.push("incomplete, cargo output unrecognized".to_owned());
+        }
+        if !notes.is_empty() {
+            write!(f, " ({})", notes.join(", "))?;
+        }
+        if self.crates > self.projects {
+            write!(
+                f,
+                ", {} crates in {} target directories",
+                self.crates, self.projects
+            )?;
+        }
+        write!(f, "{}", self.failures())?;
+        if self.interrupted {
+            write!(
+                f,
+                ", interrupted with {} projects left",
+                self.skipped.interrupted
+            )?;
+        }
+        if self.kept_latest > 0 {
+            write!(f, ", {} most recent kept", self.kept_latest)?;
+        }
+        if let Some(budget) = self.budget {
+            let budget_kib = budget as f64 / 1024.;
+            if self.size_kib >= budget_kib {
+                write!(f, ", budget of {} met", self.format_size(budget_kib))?;
+            } else {
+                write!(
+                    f,
+                    ", {} short of the {} budget",
+                    self.format_size(budget_kib - self.size_kib),
+                    self.format_size(budget_kib)
+                )?;
+            }
+        }
+        if self.hook_failures > 0 {
+            write!(f, ", {} hooks failed", self.hook_failures)?;
+        }
+        if self.pruned_targets > 0 {
+            write!(f, ", {} empty targets pruned", self.pruned_targets)?;
+        }
+        if self.lockfiles_removed > 0 {
+            write!(f, ", {} lockfiles removed", self.lockfiles_removed)?;
+        }
+        if let Some(sccache) = &self.sccache {
+            write!(
+                f,
+                ", sccache {} files, {} total",
+                sccache.files,
+                self.format_size(sccache.size_kib)
+            )?;
+        }
+        match self.disk_freed_kib {
+            Some(kib) if kib >= 0. => {
+                write!(f, ", {} actually freed on disk", self.format_size(kib))?
+            }
+            Some(kib) => write!(
+                f,
+                ", free disk space shrank by {} meanwhile",
+                self.format_size(-kib)
+            )?,
+            None => {}
+        }
+        if let Some(cargo_cache) = &self.cargo_cache {
+            write!(
+                f,
+                ", cargo cache {} files, {} total",
+                cargo_cache.files,
+                self.format_size(cargo_cache.size_kib)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Merge the totals of another run or worker into these ones.
+///
+/// Counters, records and profiles add up, `oversized` projects are appended, and
+/// `disk_freed_kib`, `sccache` and `cargo_cache` add up where either side has them. A
+/// `budget` is kept from `self` unless only `other` has one, the `size_format` always is.
+impl AddAssign for CleanupStats {
+    fn add_assign(&mut self, other: Self) {
+        fn merge_output(a: &mut Option<CleanOutput>, b: Option<CleanOutput>) {
+            match (a.as_mut(), b) {
+                (Some(a), Some(b)) => {
+                    a.files += b.files;
+                    a.size_kib += b.size_kib;
+                    a.retries += b.retries;
+                    a.measured &= b.measured;
+                }
+                (None, b) => *a = b,
+                (Some(_), None) => {}
+            }
+        }
+
+        self.projects += other.projects;
+        self.crates += other.crates;
+        self.files += other.files;
+        self.size_kib += other.size_kib;
+        self.failed += other.failed;
+        self.permission_denied.extend(other.permission_denied);
+        self.interrupted |= other.interrupted;
+        self.measured += other.measured;
+        self.kept_latest += other.kept_latest;
+        self.pruned_targets += other.pruned_targets;
+        self.lockfiles_removed += other.lockfiles_removed;
+        self.hook_failures += other.hook_failures;
+        merge_output(&mut self.sccache, other.sccache);
+        merge_output(&mut self.cargo_cache, other.cargo_cache);
+        self.records.extend(other.records);
+        for (profile, size_kib) in other.profiles {
+            *self.profiles.entry(profile).or_default() += size_kib;
+        }
+        if let Some(freed) = other.disk_freed_kib {
+            *self.disk_freed_kib.get_or_insert(0.) += freed;
+        }
+        self.output_unrecognized |= other.output_unrecognized;
+        self.budget = self.budget.or(other.budget);
+        self.oversized.extend(other.oversized);
+        self.skipped += other.skipped;
+    }
+}
+
+impl Add for CleanupStats {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for SkipTally {
+    fn add_assign(&mut self, other: Self) {
+        self.no_manifest += other.no_manifest;
+        self.no_target += other.no_target;
+        self.too_recent += other.too_recent;
+        self.too_old += other.too_old;
+        self.excluded += other.excluded;
+        self.not_workspace_root += other.not_workspace_root;
+        self.too_small += other.too_small;
+        self.shared_target += other.shared_target;
+        self.already_clean += other.already_clean;
+        self.over_budget += other.over_budget;
+        self.ignored += other.ignored;
+        self.interrupted += other.interrupted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(path: &str, files: usize, size_kib: f64) -> CleanupStats {
+        let mut stats = CleanupStats::default();
+        stats.record(
+            Path::new(path),
+            &CleanOutput {
+                files,
+                size_kib,
+                ..Default::default()
+            },
+        );
+        stats
+    }
+
+    #[test]
+    fn freed_space_is_grouped_by_directory() {
+        let mut stats = partial("/src/org-a/x", 1, 1.);
+        stats += partial("/src/org-b/y", 1, 3.);
+        stats += partial("/src/org-a/z/inner", 1, 4.);
+        stats += partial("/src", 1, 0.5);
+        stats += partial("/elsewhere/w", 1, 2.);
+        let groups = stats.grouped(Path::new("/src"), 1);
+        assert_eq!(
+            groups,
+            [
+                (PathBuf::from("org-a"), 5.),
+                (PathBuf::from("org-b"), 3.),
+                (PathBuf::from("/elsewhere/w"), 2.),
+                (PathBuf::new(), 0.5),
+            ]
+        );
+        assert_eq!(
+            stats.grouped(Path::new("/src"), 2)[0].0,
+            Path::new("org-a/z")
+        );
+    }
+
+    #[test]
+    fn merging_stats_sums_each_field() {
+        let mut total = partial("/a", 10, 1.5);
+        total.failed = 1;
+        total.skipped.already_clean = 2;
+        total.skipped.no_target = 3;
+        total.profiles.insert("debug".into(), 1.);
+
+        let mut other = partial("/b", 5, 2.5);
+        other.failed = 2;
+        other.skipped.already_clean = 1;
+        other.skipped.too_recent = 4;
+        other.profiles.insert("debug".into(), 2.);
+        other.profiles.insert("release".into(), 3.);
+        other.output_unrecognized = true;
+
+        total += other;
+        assert_eq!((total.projects, total.files, total.size_kib), (2, 15, 4.));
+        assert_eq!(total.failed, 3);
+        assert_eq!(total.skipped.already_clean, 3);
+        assert_eq!((total.skipped.no_target, total.skipped.too_recent), (3, 4));
+        assert_eq!(total.records.len(), 2);
+        assert_eq!(total.profiles["debug"], 3.);
+        assert_eq!(total.profiles["release"], 3.);
+        assert!(total.output_unrecognized);
+
+        let sum = partial("/c", 1, 1.) + partial("/d", 2, 1.);
+        assert_eq!((sum.projects, sum.files, sum.size_kib), (2, 3, 2.));
+    }
+
+    #[test]
+    fn sizes_print_with_units_or_as_bare_bytes() {
+        assert_eq!(SizeFormat::Human.format(0.5), "0.50KiB");
+        assert_eq!(SizeFormat::Human.format(1536.), "1.50MiB");
+        assert_eq!(SizeFormat::Human.format(3. * 1024. * 1024.), "3.00GiB");
+        assert_eq!(SizeFormat::Bytes.format(0.5), "512");
+        assert_eq!(SizeFormat::Bytes.format(1536.), "1572864");
+
+        let mut stats = partial("/a", 3, 2.);
+        assert_eq!(stats.totals(), "1 projects, 3 files, 2.00KiB total");
+        stats.size_format = SizeFormat::Bytes;
+        assert_eq!(stats.totals(), "1 projects, 3 files, 2048 total");
+
+        assert_eq!(stats.failures(), "");
+        stats.failed = 2;
+        stats.permission_denied.push(PathBuf::from("/b"));
+        assert_eq!(stats.failures(), ", 2 failed, 1 permission denied");
+    }
+}
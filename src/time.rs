@@ -0,0 +1,142 @@
+use crate::error::CleanError;
+use std::time::Duration;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+/// Parser for the human friendly durations accepted by `--past`.
+pub struct TimeParser;
+
+impl TimeParser {
+    /// Parse a duration such as `30m`, `12h`, `1.5d`, `2w`, `6mo` or `1y`.
+    ///
+    /// Units are case-insensitive, except that `M` means months while `m` means
+    /// minutes. Months and years are approximations: `mo` is always 30 days and `y`
+    /// always 365 days, calendar lengths and leap years are ignored.
+    pub fn parse_duration(input: &str) -> Result<Duration, CleanError> {
+        let invalid = |reason| CleanError::InvalidDuration {
+            input: input.to_owned(),
+            reason,
+        };
+        if input.starts_with('-') {
+            return Err(invalid("durations can't be negative"));
+        }
+        let split = input
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(input.len());
+        let (value, unit) = input.split_at(split);
+        if value.is_empty() || value.starts_with('.') || value.ends_with('.') {
+            return Err(invalid("expected a number followed by a unit"));
+        }
+        // units are case-insensitive except for a lone `m`: `M` is months, `m` minutes
+        let unit = match unit {
+            "M" => 30 * DAY,
+            unit => match unit.to_ascii_lowercase().as_str() {
+                "m" => MINUTE,
+                "h" => HOUR,
+                "d" => DAY,
+                "w" => 7 * DAY,
+                "mo" => 30 * DAY,
+                "y" => 365 * DAY,
+                _ => {
+                    return Err(invalid(
+                        "unknown unit, available units: m, h, d, w, mo or M, y",
+                    ));
+                }
+            },
+        };
+
+        // whole numbers stay in integer arithmetic to be exact
+        if !value.contains('.') {
+            let Some(secs) = value.parse::<u64>().ok().and_then(|v| v.checked_mul(unit)) else {
+                return Err(invalid("it is too large"));
+            };
+            return Ok(Duration::from_secs(secs));
+        }
+        let value = value
+            .parse::<f64>()
+            .map_err(|_| invalid("expected a number followed by a unit"))?;
+        Duration::try_from_secs_f64(value * unit as f64).map_err(|_| invalid("it is too large"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn months_and_years() {
+        assert_eq!(
+            TimeParser::parse_duration("6mo").unwrap(),
+            Duration::from_secs(6 * 30 * DAY)
+        );
+        assert_eq!(
+            TimeParser::parse_duration("1y").unwrap(),
+            Duration::from_secs(365 * DAY)
+        );
+    }
+
+    #[test]
+    fn units_ignore_case_but_capital_m_is_months() {
+        assert_eq!(
+            TimeParser::parse_duration("2D").unwrap(),
+            Duration::from_secs(2 * DAY)
+        );
+        assert_eq!(
+            TimeParser::parse_duration("3MO").unwrap(),
+            Duration::from_secs(3 * 30 * DAY)
+        );
+        assert_eq!(
+            TimeParser::parse_duration("3M").unwrap(),
+            Duration::from_secs(3 * 30 * DAY)
+        );
+        assert_eq!(
+            TimeParser::parse_duration("3m").unwrap(),
+            Duration::from_secs(3 * MINUTE)
+        );
+    }
+
+    #[test]
+    fn minutes_are_not_months() {
+        assert_eq!(
+            TimeParser::parse_duration("5m").unwrap(),
+            Duration::from_secs(5 * MINUTE)
+        );
+        assert_eq!(
+            TimeParser::parse_duration("5mo").unwrap(),
+            Duration::from_secs(5 * 30 * DAY)
+        );
+    }
+
+    #[test]
+    fn fractional_values() {
+        assert_eq!(
+            TimeParser::parse_duration("1.5d").unwrap(),
+            Duration::from_secs(36 * HOUR)
+        );
+        assert_eq!(
+            TimeParser::parse_duration("0.5h").unwrap(),
+            Duration::from_secs(30 * MINUTE)
+        );
+    }
+
+    #[test]
+    fn rejects_negative_and_garbage() {
+        assert!(TimeParser::parse_duration("-1d").is_err());
+        assert!(TimeParser::parse_duration("1..5d").is_err());
+        assert!(TimeParser::parse_duration(".5d").is_err());
+        assert!(TimeParser::parse_duration("1.d").is_err());
+        assert!(TimeParser::parse_duration("abc").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_units() {
+        assert!(matches!(
+            TimeParser::parse_duration("5"),
+            Err(CleanError::InvalidDuration { .. })
+        ));
+        assert!(TimeParser::parse_duration("5mon").is_err());
+        assert!(TimeParser::parse_duration("d").is_err());
+    }
+}
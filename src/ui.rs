@@ -0,0 +1,219 @@
+use console::style;
+use indicatif::{MultiProgress, ProgressBar};
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How often spinners move on their own, so a long clean doesn't look hung.
+pub const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where status and per-project lines go.
+pub enum Ui {
+    /// Spinners on stderr, an overall one plus one per parallel job, lines are printed
+    /// above them.
+    Progress(MultiProgress, ProgressBar),
+    /// Plain lines on stderr, for logs and non-interactive terminals.
+    Plain,
+    /// Only warnings and failures, as plain lines on stderr.
+    FailuresOnly,
+    /// Nothing but warnings and failures on stderr, for machine readable output on stdout.
+    Silent,
+    /// Another `Ui`, with every line and the summary also written unstyled to a file
+    /// for `--output`, whatever the other one shows.
+    Tee(Box<Ui>, Mutex<BufWriter<File>>),
+}
+
+/// A spinner of its own for one parallel job, removed once the job is done.
+#[derive(Default)]
+pub struct JobLine(Option<ProgressBar>);
+
+impl Ui {
+    pub fn progress(bar: ProgressBar) -> Self {
+        let multi = MultiProgress::new();
+        let bar = multi.add(bar);
+        Ui::Progress(multi, bar)
+    }
+
+    /// Show everything `ui` shows and also write it to a new file at `path`.
+    pub fn tee(ui: Ui, path: &Path) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(Ui::Tee(Box::new(ui), Mutex::new(file)))
+    }
+
+    /// Update the spinner, plain output skips these transient messages.
+    pub fn status(&self, prefix: impl Into<Cow<'static, str>>, msg: impl Display) {
+        match self {
+            Ui::Progress(_, pb) => {
+                pb.set_prefix(prefix);
+                pb.set_message(msg.to_string());
+            }
+            Ui::Tee(ui, _) => ui.status(prefix, msg),
+            _ => {}
+        }
+    }
+
+    /// A spinner below the overall one, a no-op without progress output.
+    pub fn job_line(&self) -> JobLine {
+        match self {
+            Ui::Progress(multi, pb) => {
+                let line = multi.add(ProgressBar::new_spinner().with_style(pb.style()));
+                line.enable_steady_tick(TICK_INTERVAL);
+                JobLine(Some(line))
+            }
+            Ui::Tee(ui, _) => ui.job_line(),
+            _ => JobLine(None),
+        }
+    }
+
+    pub fn println(&self, line: impl AsRef<str>) {
+        match self {
+            // a failing write to the terminal isn't worth aborting for
+            Ui::Progress(multi, _) => {
+                let _ = multi.println(line);
+            }
+            Ui::Plain => eprintln!("{}", line.as_ref()),
+            Ui::FailuresOnly | Ui::Silent => {}
+            Ui::Tee(ui, file) => {
+                write_plain(file, line.as_ref());
+                ui.println(line);
+            }
+        }
+    }
+
+    /// Like `println`, but also shown with `FailuresOnly` and `Silent`, always on stderr.
+    pub fn warn(&self, line: impl AsRef<str>) {
+        match self {
+            Ui::FailuresOnly | Ui::Silent => eprintln!("{}", line.as_ref()),
+            Ui::Tee(ui, file) => {
+                write_plain(file, line.as_ref());
+                ui.warn(line);
+            }
+            ui => ui.println(line),
+        }
+    }
+
+    /// Hide the spinners while `f` talks to the terminal itself, e.g. to ask a question.
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        match self {
+            Ui::Progress(multi, _) => multi.suspend(f),
+            Ui::Tee(ui, _) => ui.suspend(f),
+            _ => f(),
+        }
+    }
+
+    /// Remove the spinners without a summary line.
+    pub fn clear(&self) {
+        match self {
+            Ui::Progress(_, pb) => pb.finish_and_clear(),
+            Ui::Tee(ui, _) => ui.clear(),
+            _ => {}
+        }
+    }
+
+    /// Show the final summary line.
+    pub fn finish(&self, prefix: &'static str, msg: String) {
+        match self {
+            Ui::Progress(_, pb) => {
+                pb.set_prefix(prefix);
+                pb.finish_with_message(msg);
+            }
+            Ui::Plain => eprintln!("{:>12} {msg}", style(prefix).bold().green().for_stderr()),
+            Ui::FailuresOnly | Ui::Silent => {}
+            Ui::Tee(ui, file) => {
+                write_plain(file, &format!("{prefix:>12} {msg}"));
+                ui.finish(prefix, msg);
+            }
+        }
+    }
+
+    /// Write out what is still buffered for `--output`, the only place its errors show.
+    pub fn flush(&self) -> io::Result<()> {
+        match self {
+            Ui::Tee(_, file) => file.lock().unwrap().flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Append `line` without styling, a failing write is reported by `Ui::flush` at the latest.
+fn write_plain(file: &Mutex<BufWriter<File>>, line: &str) {
+    let _ = writeln!(file.lock().unwrap(), "{}", console::strip_ansi_codes(line));
+}
+
+impl JobLine {
+    pub fn status(&self, prefix: impl Into<Cow<'static, str>>, msg: impl Display) {
+        if let Some(pb) = &self.0 {
+            pb.set_prefix(prefix);
+            pb.set_message(msg.to_string());
+            pb.tick();
+        }
+    }
+}
+
+impl Drop for JobLine {
+    fn drop(&mut self) {
+        if let Some(pb) = &self.0 {
+            pb.finish_and_clear();
+        }
+    }
+}
+
+/// Open an inherited file descriptor for `--progress-fd`.
+///
+/// Reopening `/dev/fd/N` on Linux starts a fresh file offset, so it is opened for
+/// appending, otherwise a log file the wrapper set up would be written over from the start.
+pub fn open_progress_fd(fd: u32) -> io::Result<File> {
+    if cfg!(unix) {
+        OpenOptions::new()
+            .append(true)
+            .open(format!("/dev/fd/{fd}"))
+    } else {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Write a `done/total` line for `--progress-fd`, a reader that went away is ignored.
+pub fn report_progress(file: &File, done: usize, total: usize) {
+    let _ = writeln!(&*file, "{done}/{total}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[cfg(unix)]
+    #[test]
+    fn progress_fd_appends_to_what_is_there() {
+        use std::os::fd::AsRawFd;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("progress.log");
+        fs::write(&path, "started\n").unwrap();
+        let inherited = File::open(&path).unwrap();
+        let file = open_progress_fd(inherited.as_raw_fd() as u32).unwrap();
+        report_progress(&file, 1, 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "started\n1/2\n");
+    }
+
+    #[test]
+    fn tee_writes_unstyled_lines_even_when_silent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.log");
+        let ui = Ui::tee(Ui::Silent, &path).unwrap();
+        ui.println(format!(
+            "{} 1 files",
+            style("Removed").force_styling(true).green()
+        ));
+        ui.finish("Cleaned", "1 projects".to_owned());
+        ui.flush().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "Removed 1 files\n     Cleaned 1 projects\n"
+        );
+    }
+}